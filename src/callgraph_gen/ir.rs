@@ -0,0 +1,916 @@
+//! Reads what the callgraph needs from the LLVM IR of a module: the call sites of every
+//! function, the vtables, and the functions whose address is taken
+
+use std::collections::{HashMap, HashSet};
+
+use llvm_ir::function::ParameterAttribute;
+use llvm_ir::instruction::{GetElementPtr, Instruction};
+use llvm_ir::terminator::Terminator;
+use llvm_ir::{Constant, DebugLoc, Function, Module, Name, Operand, Type, TypeRef};
+use rustc_demangle::demangle;
+
+/// What a call site calls
+#[derive(Debug, PartialEq, Eq)]
+pub enum Callee<'m> {
+    /// A function named at the call site, possibly through constant casts and aliases. This is
+    /// the mangled name of the function.
+    Function(&'m str),
+    /// An `llvm.*` intrinsic, which is not a function of the program
+    Intrinsic(&'m str),
+    /// A call through a function pointer
+    Indirect {
+        /// The byte offset of the vtable slot the pointer is loaded from, if it comes from a
+        /// vtable
+        vtable_offset: Option<u64>,
+        /// The LLVM type of the called function
+        signature: String,
+    },
+    /// Inline assembly, or a constant callee that is not a function
+    Unknown,
+}
+
+/// A `call`, `invoke` or `callbr` instruction
+pub struct CallSite<'m> {
+    pub callee: Callee<'m>,
+    pub debugloc: Option<&'m DebugLoc>,
+    /// Whether the call is inside a loop of the calling function
+    pub in_loop: bool,
+}
+
+/// The basic blocks a terminator may branch to
+fn successors(term: &Terminator) -> Vec<&Name> {
+    match term {
+        Terminator::Br(br) => vec![&br.dest],
+        Terminator::CondBr(br) => vec![&br.true_dest, &br.false_dest],
+        Terminator::Switch(switch) => switch
+            .dests
+            .iter()
+            .map(|(_, dest)| dest)
+            .chain([&switch.default_dest])
+            .collect(),
+        Terminator::IndirectBr(br) => br.possible_dests.iter().collect(),
+        Terminator::Invoke(invoke) => vec![&invoke.return_label, &invoke.exception_label],
+        // The labels an `asm goto` may jump to are not exposed by `llvm_ir`, only the label
+        // normal returns continue at
+        Terminator::CallBr(callbr) => vec![&callbr.return_label],
+        Terminator::CatchSwitch(switch) => switch
+            .catch_handlers
+            .iter()
+            .chain(switch.default_unwind_dest.as_ref())
+            .collect(),
+        Terminator::CatchRet(ret) => vec![&ret.successor],
+        Terminator::CleanupRet(ret) => ret.unwind_dest.iter().collect(),
+        _ => vec![],
+    }
+}
+
+/// Finds the strongly connected components of a graph with Tarjan's algorithm, without
+/// recursion since control flow graphs can be deep. Returns the component of every node.
+fn strongly_connected_components(successors: &[Vec<usize>]) -> Vec<usize> {
+    const UNVISITED: usize = usize::MAX;
+    let node_count = successors.len();
+    let mut index = vec![UNVISITED; node_count];
+    let mut lowlink = vec![0; node_count];
+    let mut on_stack = vec![false; node_count];
+    let mut stack = Vec::new();
+    let mut component = vec![0; node_count];
+    let mut next_index = 0;
+    let mut next_component = 0;
+    for root in 0..node_count {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        index[root] = next_index;
+        lowlink[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        // Every node being visited, with how many of its successors were visited already
+        let mut visiting = vec![(root, 0)];
+        while let Some((node, visited)) = visiting.last_mut() {
+            let node = *node;
+            if let Some(&successor) = successors[node].get(*visited) {
+                *visited += 1;
+                if index[successor] == UNVISITED {
+                    index[successor] = next_index;
+                    lowlink[successor] = next_index;
+                    next_index += 1;
+                    stack.push(successor);
+                    on_stack[successor] = true;
+                    visiting.push((successor, 0));
+                } else if on_stack[successor] {
+                    lowlink[node] = lowlink[node].min(index[successor]);
+                }
+                continue;
+            }
+            visiting.pop();
+            if let Some((parent, _)) = visiting.last() {
+                lowlink[*parent] = lowlink[*parent].min(lowlink[node]);
+            }
+            if lowlink[node] == index[node] {
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component[member] = next_component;
+                    if member == node {
+                        break;
+                    }
+                }
+                next_component += 1;
+            }
+        }
+    }
+    component
+}
+
+/// The basic blocks of `fun` that are part of a loop: those in a strongly connected component
+/// of its control flow graph with more than one block, and those branching to themselves
+fn blocks_in_loops(fun: &Function) -> HashSet<&Name> {
+    let block_index: HashMap<&Name, usize> = fun
+        .basic_blocks
+        .iter()
+        .enumerate()
+        .map(|(index, bb)| (&bb.name, index))
+        .collect();
+    let successors: Vec<Vec<usize>> = fun
+        .basic_blocks
+        .iter()
+        .map(|bb| {
+            successors(&bb.term)
+                .into_iter()
+                .filter_map(|name| block_index.get(name).copied())
+                .collect()
+        })
+        .collect();
+    let component = strongly_connected_components(&successors);
+    let mut component_size: HashMap<usize, usize> = HashMap::new();
+    for component in &component {
+        *component_size.entry(*component).or_default() += 1;
+    }
+    fun.basic_blocks
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| {
+            component_size[&component[*index]] > 1 || successors[*index].contains(index)
+        })
+        .map(|(_, bb)| &bb.name)
+        .collect()
+}
+
+/// The number of bytes of a constant that holds plain data, like the size and alignment in a
+/// vtable
+fn constant_size(constant: &Constant) -> Option<u64> {
+    match constant {
+        Constant::Int { bits, .. } => Some(u64::from(*bits) / 8),
+        Constant::Array { elements, .. } => {
+            elements.iter().map(|element| constant_size(element)).sum()
+        }
+        Constant::AggregateZero(ty) => match &**ty {
+            Type::ArrayType {
+                element_type,
+                num_elements,
+            } if matches!(**element_type, Type::IntegerType { bits: 8 }) => {
+                u64::try_from(*num_elements).ok()
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The value of an index operand of a `getelementptr`, if it is a constant
+fn constant_index(index: &Operand) -> Option<u64> {
+    match index {
+        Operand::ConstantOperand(constant) => match &**constant {
+            Constant::Int { value, .. } => Some(*value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The operands of `instr` through which the address of a function may escape. Calling a
+/// function does not take its address, so this leaves out the callee.
+fn escaping_operands(instr: &Instruction) -> Vec<&Operand> {
+    match instr {
+        Instruction::Store(store) => vec![&store.value],
+        Instruction::Call(call) => call.arguments.iter().map(|(arg, _)| arg).collect(),
+        Instruction::InsertValue(insert) => vec![&insert.element],
+        Instruction::Select(select) => vec![&select.true_value, &select.false_value],
+        Instruction::Phi(phi) => phi.incoming_values.iter().map(|(value, _)| value).collect(),
+        Instruction::PtrToInt(cast) => vec![&cast.operand],
+        _ => vec![],
+    }
+}
+
+/// The same for terminators
+fn escaping_terminator_operands(term: &Terminator) -> Vec<&Operand> {
+    match term {
+        Terminator::Invoke(invoke) => invoke.arguments.iter().map(|(arg, _)| arg).collect(),
+        Terminator::Ret(ret) => ret.return_operand.iter().collect(),
+        _ => vec![],
+    }
+}
+
+/// Reads one module
+pub struct ModuleReader<'m> {
+    module: &'m Module,
+    /// Maps the name of every alias to what it aliases
+    aliases: HashMap<&'m str, &'m Constant>,
+    /// The names of the functions the module defines or declares
+    functions: HashSet<&'m str>,
+    /// The number of bytes in a pointer, which is also the size of a vtable slot
+    pointer_size: u64,
+}
+
+impl<'m> ModuleReader<'m> {
+    pub fn new(module: &'m Module) -> Self {
+        let aliases = module
+            .global_aliases
+            .iter()
+            .filter_map(|alias| match &alias.name {
+                Name::Name(name) => Some((name.as_str(), &*alias.aliasee)),
+                Name::Number(_) => None,
+            })
+            .collect();
+        let functions = module
+            .functions
+            .iter()
+            .map(|fun| fun.name.as_str())
+            .chain(
+                module
+                    .func_declarations
+                    .iter()
+                    .map(|decl| decl.name.as_str()),
+            )
+            .collect();
+        Self {
+            module,
+            aliases,
+            functions,
+            pointer_size: u64::from(module.data_layout.alignments.ptr_alignment(0).size / 8),
+        }
+    }
+
+    /// The mangled name of the function `constant` refers to, seeing through casts and aliases.
+    /// References to global variables, like other vtables, are not functions.
+    fn function_name(&self, constant: &'m Constant) -> Option<&'m str> {
+        match constant {
+            Constant::GlobalReference {
+                name: Name::Name(name),
+                ..
+            } => self.aliases.get(name.as_str()).map_or_else(
+                || self.functions.get(name.as_str()).copied(),
+                |aliasee| self.function_name(aliasee),
+            ),
+            Constant::BitCast(cast) => self.function_name(&cast.operand),
+            Constant::AddrSpaceCast(cast) => self.function_name(&cast.operand),
+            _ => None,
+        }
+    }
+
+    /// If `initializer` looks like a vtable, returns the byte offset and label of every
+    /// function in it. Rust lays vtables out as the drop glue (null if there is none), the size
+    /// and alignment of the type, then one pointer per trait method (null for methods that
+    /// cannot be called).
+    fn vtable_functions(&self, initializer: &'m Constant) -> Option<Vec<(u64, String)>> {
+        let Constant::Struct { values, .. } = initializer else {
+            return None;
+        };
+        let header_size = 2 * self.pointer_size;
+        let mut functions = Vec::new();
+        let mut offset = 0;
+        let mut header_bytes = 0;
+        for (index, value) in values.iter().enumerate() {
+            if let Some(name) = self.function_name(value) {
+                let label = demangle(name).to_string();
+                let in_place = if index == 0 {
+                    label.starts_with("core::ptr::drop_in_place<")
+                } else {
+                    header_bytes == header_size
+                };
+                if !in_place {
+                    return None;
+                }
+                functions.push((offset, label));
+                offset += self.pointer_size;
+            } else if let Constant::Null(_) = &**value {
+                offset += self.pointer_size;
+            } else if header_bytes == header_size
+                && matches!(&**value, Constant::GlobalReference { .. })
+            {
+                // Vtables of traits with supertraits point to the vtables of the supertraits
+                offset += self.pointer_size;
+            } else if index > 0 && header_bytes < header_size {
+                let size = constant_size(value)?;
+                header_bytes += size;
+                offset += size;
+            } else {
+                return None;
+            }
+        }
+        (header_bytes == header_size && !functions.is_empty()).then_some(functions)
+    }
+
+    /// The byte offset and label of every function in every vtable of the module
+    pub fn vtable_slots(&self) -> Vec<(u64, String)> {
+        self.module
+            .global_vars
+            .iter()
+            .filter(|global| global.is_constant)
+            .filter_map(|global| global.initializer.as_ref())
+            .filter_map(|initializer| self.vtable_functions(initializer))
+            .flatten()
+            .collect()
+    }
+
+    /// Records every function `constant` refers to as address-taken, with its signature
+    fn take_addresses(&self, constant: &'m Constant, address_taken: &mut HashMap<String, String>) {
+        match constant {
+            Constant::GlobalReference { ty, .. } if matches!(**ty, Type::FuncType { .. }) => {
+                if let Some(name) = self.function_name(constant) {
+                    address_taken.insert(demangle(name).to_string(), ty.to_string());
+                }
+            }
+            Constant::Struct {
+                values: elements, ..
+            }
+            | Constant::Array { elements, .. }
+            | Constant::Vector(elements) => {
+                for element in elements {
+                    self.take_addresses(element, address_taken);
+                }
+            }
+            Constant::BitCast(cast) => self.take_addresses(&cast.operand, address_taken),
+            Constant::AddrSpaceCast(cast) => self.take_addresses(&cast.operand, address_taken),
+            Constant::PtrToInt(cast) => self.take_addresses(&cast.operand, address_taken),
+            _ => {}
+        }
+    }
+
+    /// Maps every function whose address escapes somewhere in the module (into a variable, an
+    /// argument, a constant table...) to its signature
+    pub fn address_taken(&self) -> HashMap<String, String> {
+        let mut address_taken = HashMap::new();
+        for global in &self.module.global_vars {
+            if let Some(initializer) = &global.initializer {
+                // Functions in vtables are only called through them, anything else in a global
+                // may be loaded as a function pointer
+                if !global.is_constant || self.vtable_functions(initializer).is_none() {
+                    self.take_addresses(initializer, &mut address_taken);
+                }
+            }
+        }
+        for fun in &self.module.functions {
+            for bb in &fun.basic_blocks {
+                let operands = bb
+                    .instrs
+                    .iter()
+                    .flat_map(escaping_operands)
+                    .chain(escaping_terminator_operands(&bb.term));
+                for operand in operands {
+                    if let Operand::ConstantOperand(constant) = operand {
+                        self.take_addresses(constant, &mut address_taken);
+                    }
+                }
+            }
+        }
+        address_taken
+    }
+
+    /// If `callee` is a function pointer loaded from a vtable, returns the byte offset of its
+    /// slot. Rust calls `dyn Trait` methods with
+    /// `load ptr, ptr (getelementptr i8, ptr %vtable, OFFSET)`, and the drop glue with
+    /// `load ptr, ptr %vtable`, passing the data pointer of the same `dyn` pointer as
+    /// `receiver`, the first argument. Other loads, even at constant offsets, are from function
+    /// pointers in struct fields or local variables.
+    fn vtable_offset(
+        &self,
+        fun: &Function,
+        defs: &HashMap<&'m Name, &'m Instruction>,
+        callee: &Operand,
+        receiver: Option<&Operand>,
+    ) -> Option<u64> {
+        let Operand::LocalOperand { name, .. } = callee else {
+            return None;
+        };
+        let Instruction::Load(load) = defs.get(name)? else {
+            return None;
+        };
+        let Operand::LocalOperand { name: address, .. } = &load.address else {
+            return None;
+        };
+        let (vtable, offset) = match defs.get(address) {
+            Some(Instruction::GetElementPtr(gep)) => {
+                let Operand::LocalOperand { name: base, .. } = &gep.address else {
+                    return None;
+                };
+                let [index] = gep.indices.as_slice() else {
+                    return None;
+                };
+                let index = constant_index(index)?;
+                let offset = match &*gep.source_element_type {
+                    Type::IntegerType { bits: 8 } => index,
+                    Type::PointerType { .. } => index * self.pointer_size,
+                    _ => return None,
+                };
+                (base, offset)
+            }
+            _ => (address, 0),
+        };
+        self.is_fat_pointer(fun, defs, receiver?, vtable)
+            .then_some(offset)
+    }
+
+    /// Whether `data` and `vtable` are the two halves of the same `dyn` pointer, which is
+    /// passed as two arguments, extracted from a pair, or loaded from a pair in memory. Unlike a
+    /// reference to a sized value, the data of a `dyn` reference is not `dereferenceable`.
+    fn is_fat_pointer(
+        &self,
+        fun: &Function,
+        defs: &HashMap<&'m Name, &'m Instruction>,
+        data: &Operand,
+        vtable: &Name,
+    ) -> bool {
+        let Operand::LocalOperand { name: data, .. } = data else {
+            return false;
+        };
+        match (defs.get(data), defs.get(vtable)) {
+            (None, None) => fun.parameters.windows(2).any(|pair| match pair {
+                [first, second] => {
+                    first.name == *data
+                        && second.name == *vtable
+                        && !first.attributes.iter().any(|attribute| {
+                            matches!(attribute, ParameterAttribute::Dereferenceable(_))
+                        })
+                }
+                _ => false,
+            }),
+            (Some(Instruction::ExtractValue(data)), Some(Instruction::ExtractValue(vtable))) => {
+                data.aggregate == vtable.aggregate && data.indices == [0] && vtable.indices == [1]
+            }
+            (Some(Instruction::Load(data)), Some(Instruction::Load(vtable))) => {
+                let Operand::LocalOperand { name: second, .. } = &vtable.address else {
+                    return false;
+                };
+                matches!(
+                    defs.get(second),
+                    Some(Instruction::GetElementPtr(gep))
+                        if gep.address == data.address && self.is_second_pointer(gep)
+                )
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `gep` addresses the second of two pointers
+    fn is_second_pointer(&self, gep: &GetElementPtr) -> bool {
+        let indices: Vec<Option<u64>> = gep.indices.iter().map(constant_index).collect();
+        match (&*gep.source_element_type, indices.as_slice()) {
+            (Type::IntegerType { bits: 8 }, [Some(offset)]) => *offset == self.pointer_size,
+            (Type::PointerType { .. }, [Some(1)]) => true,
+            (Type::StructType { element_types, .. }, [Some(0), Some(1)]) => {
+                element_types.len() == 2
+            }
+            _ => false,
+        }
+    }
+
+    /// Classifies what a call site calls. `function` is the callee operand, `None` for inline
+    /// assembly, `receiver` its first argument, and `defs` maps local names to the instructions
+    /// of `fun`, the caller, defining them.
+    fn callee(
+        &self,
+        fun: &Function,
+        function: Option<&'m Operand>,
+        receiver: Option<&Operand>,
+        signature: &TypeRef,
+        defs: &HashMap<&'m Name, &'m Instruction>,
+    ) -> Callee<'m> {
+        match function {
+            Some(Operand::ConstantOperand(constant)) => match self.function_name(constant) {
+                Some(name) if name.starts_with("llvm.") => Callee::Intrinsic(name),
+                Some(name) => Callee::Function(name),
+                None => Callee::Unknown,
+            },
+            Some(callee @ Operand::LocalOperand { .. }) => Callee::Indirect {
+                vtable_offset: self.vtable_offset(fun, defs, callee, receiver),
+                signature: signature.to_string(),
+            },
+            _ => Callee::Unknown,
+        }
+    }
+
+    /// Every call site in `fun`, in order. Calls made several times are listed every time.
+    pub fn call_sites(&self, fun: &'m Function) -> Vec<CallSite<'m>> {
+        // Function pointers are loaded by instructions of the same function, find them by name
+        let defs: HashMap<&Name, &Instruction> = fun
+            .basic_blocks
+            .iter()
+            .flat_map(|bb| &bb.instrs)
+            .filter_map(|instr| instr.try_get_result().map(|name| (name, instr)))
+            .collect();
+        let blocks_in_loops = blocks_in_loops(fun);
+        let mut call_sites = Vec::new();
+        for bb in &fun.basic_blocks {
+            let in_loop = blocks_in_loops.contains(&bb.name);
+            for instr in &bb.instrs {
+                if let Instruction::Call(call) = instr {
+                    call_sites.push(CallSite {
+                        callee: self.callee(
+                            fun,
+                            call.function.as_ref().right(),
+                            call.arguments.first().map(|(argument, _)| argument),
+                            &call.function_ty,
+                            &defs,
+                        ),
+                        debugloc: call.debugloc.as_ref(),
+                        in_loop,
+                    });
+                }
+            }
+            let (function, arguments, signature, debugloc) = match &bb.term {
+                Terminator::Invoke(invoke) => (
+                    invoke.function.as_ref().right(),
+                    &invoke.arguments,
+                    invoke.function_ty.clone(),
+                    invoke.debugloc.as_ref(),
+                ),
+                // `callbr` does not record the type of its callee, so it is taken from the
+                // callee operand
+                Terminator::CallBr(callbr) => (
+                    callbr.function.as_ref().right(),
+                    &callbr.arguments,
+                    self.module.type_of(&callbr.function),
+                    callbr.debugloc.as_ref(),
+                ),
+                _ => continue,
+            };
+            call_sites.push(CallSite {
+                callee: self.callee(
+                    fun,
+                    function,
+                    arguments.first().map(|(argument, _)| argument),
+                    &signature,
+                    &defs,
+                ),
+                debugloc,
+                in_loop,
+            });
+        }
+        call_sites
+    }
+}
+
+/// Parses a module from textual IR
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+pub fn module_from_ir(ir: &str) -> Module {
+    use std::io::Write;
+
+    let mut file = tempfile::Builder::new().suffix(".ll").tempfile().unwrap();
+    file.write_all(ir.as_bytes()).unwrap();
+    Module::from_ir_path(file.path()).unwrap()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn callees<'m>(reader: &ModuleReader<'m>, module: &'m Module, fun: &str) -> Vec<Callee<'m>> {
+        let fun = module.get_func_by_name(fun).unwrap();
+        reader
+            .call_sites(fun)
+            .into_iter()
+            .map(|call_site| call_site.callee)
+            .collect()
+    }
+
+    /// The vtable offsets of the indirect calls in `fun`
+    fn vtable_offsets(reader: &ModuleReader, module: &Module, fun: &str) -> Vec<Option<u64>> {
+        callees(reader, module, fun)
+            .into_iter()
+            .filter_map(|callee| match callee {
+                Callee::Indirect { vtable_offset, .. } => Some(vtable_offset),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_direct_calls_through_aliases_and_intrinsics() {
+        let module = module_from_ir(
+            r"
+            @alias = alias void (), ptr @callee
+
+            define void @callee() {
+              ret void
+            }
+
+            declare void @llvm.donothing()
+
+            define void @caller() {
+              call void @callee()
+              call void @callee()
+              call void @alias()
+              call void @llvm.donothing()
+              ret void
+            }
+            ",
+        );
+        let reader = ModuleReader::new(&module);
+        assert_eq!(
+            callees(&reader, &module, "caller"),
+            vec![
+                Callee::Function("callee"),
+                Callee::Function("callee"),
+                Callee::Function("callee"),
+                Callee::Intrinsic("llvm.donothing"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calls_in_loops() {
+        let module = module_from_ir(
+            r"
+            declare void @before()
+            declare void @in_loop()
+            declare void @after()
+
+            define void @caller(i1 %again) {
+            entry:
+              call void @before()
+              br label %header
+            header:
+              br label %body
+            body:
+              call void @in_loop()
+              br i1 %again, label %header, label %exit
+            exit:
+              call void @after()
+              ret void
+            }
+
+            define void @spin(i1 %again) {
+            entry:
+              br label %spin
+            spin:
+              call void @in_loop()
+              br i1 %again, label %spin, label %exit
+            exit:
+              ret void
+            }
+            ",
+        );
+        let reader = ModuleReader::new(&module);
+        for (fun, expected) in [("caller", vec![false, true, false]), ("spin", vec![true])] {
+            let in_loop: Vec<bool> = reader
+                .call_sites(module.get_func_by_name(fun).unwrap())
+                .iter()
+                .map(|call_site| call_site.in_loop)
+                .collect();
+            assert_eq!(in_loop, expected, "{fun}");
+        }
+    }
+
+    #[test]
+    fn test_strongly_connected_components() {
+        // 0 -> 1 <-> 2 -> 3, 3 -> 3
+        let component = strongly_connected_components(&[vec![1], vec![2], vec![1, 3], vec![3]]);
+        assert_eq!(component[1], component[2]);
+        assert_ne!(component[0], component[1]);
+        assert_ne!(component[3], component[1]);
+    }
+
+    #[test]
+    fn test_invoke() {
+        let module = module_from_ir(
+            r"
+            declare void @may_unwind()
+            declare i32 @personality(...)
+
+            define void @caller() personality ptr @personality {
+            entry:
+              invoke void @may_unwind()
+                      to label %ok unwind label %cleanup
+            ok:
+              ret void
+            cleanup:
+              %lp = landingpad { ptr, i32 } cleanup
+              resume { ptr, i32 } %lp
+            }
+            ",
+        );
+        let reader = ModuleReader::new(&module);
+        assert_eq!(
+            callees(&reader, &module, "caller"),
+            vec![Callee::Function("may_unwind")]
+        );
+        assert!(reader.address_taken().is_empty());
+    }
+
+    #[test]
+    fn test_vtables_and_function_pointers() {
+        let module = module_from_ir(
+            r#"
+            @vtable = private unnamed_addr constant <{ ptr, [16 x i8], ptr, ptr }> <{
+              ptr @"_ZN4core3ptr29drop_in_place$LT$foo..Foo$GT$17h0123456789abcdefE",
+              [16 x i8] c"\08\00\00\00\00\00\00\00\08\00\00\00\00\00\00\00",
+              ptr @method_a,
+              ptr @method_b
+            }>
+            @callbacks = constant [1 x ptr] [ptr @callback]
+
+            define void @"_ZN4core3ptr29drop_in_place$LT$foo..Foo$GT$17h0123456789abcdefE"(ptr %self) {
+              ret void
+            }
+            define i64 @method_a(ptr %self) {
+              ret i64 0
+            }
+            define i64 @method_b(ptr %self, i64 %x) {
+              ret i64 %x
+            }
+            define void @callback(i32 %x) {
+              ret void
+            }
+
+            define i64 @dyn_call(ptr %data, ptr %vtable) {
+              %slot = getelementptr inbounds i8, ptr %vtable, i64 24
+              %method = load ptr, ptr %slot
+              %r = call i64 %method(ptr %data)
+              ret i64 %r
+            }
+
+            define void @pointer_call(ptr %f) {
+              call void %f(i32 1)
+              ret void
+            }
+            "#,
+        );
+        let reader = ModuleReader::new(&module);
+        let signature = |fun: &str| {
+            module
+                .type_of(module.get_func_by_name(fun).unwrap())
+                .to_string()
+        };
+
+        let mut vtable_slots = reader.vtable_slots();
+        vtable_slots.sort();
+        assert_eq!(
+            vtable_slots,
+            vec![
+                (
+                    0,
+                    "core::ptr::drop_in_place<foo::Foo>::h0123456789abcdef".to_string()
+                ),
+                (24, "method_a".to_string()),
+                (32, "method_b".to_string()),
+            ]
+        );
+        assert_eq!(
+            callees(&reader, &module, "dyn_call"),
+            vec![Callee::Indirect {
+                vtable_offset: Some(24),
+                signature: signature("method_a"),
+            }]
+        );
+
+        assert_eq!(
+            reader.address_taken(),
+            HashMap::from([("callback".to_string(), signature("callback"))])
+        );
+        assert_eq!(
+            callees(&reader, &module, "pointer_call"),
+            vec![Callee::Indirect {
+                vtable_offset: None,
+                signature: signature("callback"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_vtables_pointing_to_other_globals() {
+        let module = module_from_ir(
+            r#"
+            @super_vtable = private unnamed_addr constant <{ ptr, [16 x i8], ptr }> <{
+              ptr null,
+              [16 x i8] c"\00\00\00\00\00\00\00\00\01\00\00\00\00\00\00\00",
+              ptr @method_a
+            }>
+            @sub_vtable = private unnamed_addr constant <{ ptr, [16 x i8], ptr, ptr, ptr }> <{
+              ptr null,
+              [16 x i8] c"\00\00\00\00\00\00\00\00\01\00\00\00\00\00\00\00",
+              ptr @method_a,
+              ptr @super_vtable,
+              ptr @method_b
+            }>
+            @alloc_0 = private unnamed_addr constant [4 x i8] c"text"
+            @table = constant [2 x ptr] [ptr @alloc_0, ptr @callback]
+
+            define i64 @method_a(ptr %self) {
+              ret i64 0
+            }
+            declare i64 @method_b(ptr)
+            define void @callback(i32 %x) {
+              ret void
+            }
+            "#,
+        );
+        let reader = ModuleReader::new(&module);
+        let mut vtable_slots = reader.vtable_slots();
+        vtable_slots.sort();
+        assert_eq!(
+            vtable_slots,
+            vec![
+                (24, "method_a".to_string()),
+                (24, "method_a".to_string()),
+                (40, "method_b".to_string()),
+            ]
+        );
+        assert_eq!(
+            reader.address_taken().into_keys().collect::<Vec<_>>(),
+            ["callback"]
+        );
+    }
+
+    #[test]
+    fn test_drop_glue_calls() {
+        let module = module_from_ir(
+            r"
+            define void @drop_boxed(ptr %boxed) {
+              %data = load ptr, ptr %boxed
+              %vtable.field = getelementptr inbounds i8, ptr %boxed, i64 8
+              %vtable = load ptr, ptr %vtable.field
+              %drop = load ptr, ptr %vtable
+              call void %drop(ptr %data)
+              ret void
+            }
+
+            define void @drop_pair({ ptr, ptr } %pair) {
+              %data = extractvalue { ptr, ptr } %pair, 0
+              %vtable = extractvalue { ptr, ptr } %pair, 1
+              %drop = load ptr, ptr %vtable
+              call void %drop(ptr %data)
+              ret void
+            }
+
+            define void @call_by_ref(ptr dereferenceable(4) %data, ptr dereferenceable(8) %f) {
+              %callback = load ptr, ptr %f
+              call void %callback(ptr %data)
+              call void %callback(i32 1)
+              ret void
+            }
+            ",
+        );
+        let reader = ModuleReader::new(&module);
+        assert_eq!(
+            vtable_offsets(&reader, &module, "drop_boxed"),
+            vec![Some(0)]
+        );
+        assert_eq!(vtable_offsets(&reader, &module, "drop_pair"), vec![Some(0)]);
+        // `%f` is a `&fn(&i32)`, not a vtable
+        assert_eq!(
+            vtable_offsets(&reader, &module, "call_by_ref"),
+            vec![None, None]
+        );
+    }
+
+    #[test]
+    fn test_function_pointer_fields_next_to_vtables() {
+        let module = module_from_ir(
+            r"
+            define i64 @field_then_dyn(ptr dereferenceable(16) %s, ptr %data, ptr %vtable) {
+              %field = getelementptr inbounds i8, ptr %s, i64 8
+              %callback = load ptr, ptr %field
+              call void %callback(ptr %s)
+              %slot = getelementptr inbounds i8, ptr %vtable, i64 24
+              %method = load ptr, ptr %slot
+              %r = call i64 %method(ptr %data)
+              ret i64 %r
+            }
+
+            define i64 @boxed_dyn({ ptr, ptr } %pair) {
+              %data = extractvalue { ptr, ptr } %pair, 0
+              %vtable = extractvalue { ptr, ptr } %pair, 1
+              %slot = getelementptr inbounds ptr, ptr %vtable, i64 4
+              %method = load ptr, ptr %slot
+              %r = call i64 %method(ptr %data)
+              %field = getelementptr inbounds ptr, ptr %data, i64 4
+              %callback = load ptr, ptr %field
+              call void %callback(ptr %data)
+              ret i64 %r
+            }
+            ",
+        );
+        let reader = ModuleReader::new(&module);
+        assert_eq!(
+            vtable_offsets(&reader, &module, "field_then_dyn"),
+            vec![None, Some(24)]
+        );
+        assert_eq!(
+            vtable_offsets(&reader, &module, "boxed_dyn"),
+            vec![Some(32), None]
+        );
+    }
+}
@@ -0,0 +1,1397 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use llvm_ir::{DebugLoc, HasDebugLoc, Module};
+use regex::Regex;
+use rustc_demangle::demangle;
+use utils::{CallEdge, CallKind, CallSites, IndirectCall, LabelInfo};
+
+use crate::unsafe_site::{OpCategory, UnsafeKind, UnsafeSite};
+use crate::utils;
+
+mod ir;
+
+use ir::{Callee, ModuleReader};
+
+/// The badness of a function and where it comes from
+#[derive(Clone, Default)]
+pub struct Badness {
+    pub total: u32,
+    /// How much of `total` comes from unsafe sites in each crate, see `SiteMatches::crate_key`
+    pub by_crate: BTreeMap<String, u32>,
+    /// How much of `total` comes from unsafe sites that are only reached through indirect calls
+    pub indirect: u32,
+    /// The labels of the root targets the function belongs to
+    pub targets: BTreeSet<String>,
+    pub label_info: LabelInfo,
+}
+
+impl Badness {
+    fn new(label_info: &LabelInfo) -> Self {
+        Self {
+            label_info: label_info.clone(),
+            ..Self::default()
+        }
+    }
+
+    fn add(&mut self, crate_name: &str, weight: u32) {
+        self.total = self.total.saturating_add(weight);
+        let by_crate = self.by_crate.entry(crate_name.to_string()).or_default();
+        *by_crate = by_crate.saturating_add(weight);
+    }
+}
+
+/// Maps the short label of every function in the root crate to its badness
+pub type BadnessMap = HashMap<String, Badness>;
+
+/// A target of the root package, e.g. its library or one of its binaries
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RootTarget {
+    /// The name rustc compiles the target under, which prefixes the labels of its functions
+    pub crate_name: String,
+    /// How the target is shown in reports, e.g. `lib` or `bin foo`
+    pub label: String,
+}
+
+/// A crate of the build whose bitcode goes into the callgraph
+#[derive(Clone, Debug)]
+pub struct BitcodeUnit {
+    pub path: PathBuf,
+    /// The label of the root target the crate is, `None` for dependencies
+    pub target: Option<String>,
+}
+
+/// Tells which root targets a function belongs to
+struct RootTargets<'t> {
+    targets: Vec<(&'t RootTarget, Regex)>,
+}
+
+impl<'t> RootTargets<'t> {
+    fn new(targets: &'t [RootTarget]) -> Self {
+        Self {
+            targets: targets
+                .iter()
+                .map(|target| (target, crate_regex(&target.crate_name)))
+                .collect(),
+        }
+    }
+
+    /// The root targets whose crate the function is in. A library and a binary of the same
+    /// package usually share their crate name, so if the function was compiled into some of
+    /// them, only those are returned. Empty if the function is not in the root package.
+    fn of(&self, label_info: &LabelInfo) -> BTreeSet<&'t str> {
+        let Some(short_label) = &label_info.short_label else {
+            return BTreeSet::new();
+        };
+        let by_name: BTreeSet<&str> = self
+            .targets
+            .iter()
+            .filter(|(_, re)| re.is_match(short_label))
+            .map(|(target, _)| target.label.as_str())
+            .collect();
+        let compiled_into: BTreeSet<&str> = by_name
+            .iter()
+            .copied()
+            .filter(|target| label_info.targets.contains(*target))
+            .collect();
+        if compiled_into.is_empty() {
+            by_name
+        } else {
+            compiled_into
+        }
+    }
+}
+
+// emit llvm IR. disable optimizations. just want debug info and call graph...
+#[allow(dead_code)]
+pub const RUSTFLAGS: &str = "-C lto=no -C opt-level=0 -C debuginfo=2 --emit=llvm-bc";
+
+/// Indexes which functions have code on which source lines
+#[derive(Default)]
+struct LineIndexer {
+    file_to_line_to_labels: HashMap<PathBuf, HashMap<u32, HashSet<String>>>,
+    /// Debug locations repeat the same few files over and over, so cache their canonical paths.
+    /// `None` for files that do not exist on this machine, like the standard library's.
+    canonical_paths: HashMap<PathBuf, Option<PathBuf>>,
+}
+
+impl LineIndexer {
+    /// The canonical path of the file `debugloc` points to, if it exists
+    fn canonical_path(&mut self, debugloc: &DebugLoc) -> Option<PathBuf> {
+        self.canonical_paths
+            .entry(utils::debugloc_path(debugloc))
+            .or_insert_with_key(|path| path.canonicalize().ok())
+            .clone()
+    }
+
+    /// Remembers that `label` has code on the line `debugloc` points to
+    fn index(&mut self, label: &str, debugloc: &DebugLoc) {
+        if let Some(canonical) = self.canonical_path(debugloc) {
+            let labels = self
+                .file_to_line_to_labels
+                .entry(canonical)
+                .or_default()
+                .entry(debugloc.line)
+                .or_default();
+            if !labels.contains(label) {
+                labels.insert(label.to_string());
+            }
+        }
+    }
+}
+
+/// Builds the callgraph of a single module. `target` is the label of the root target the
+/// module belongs to, if any.
+fn parse_module(module: &Module, target: Option<&str>) -> anyhow::Result<utils::CallGraph> {
+    // removes hex identifiers for short ids
+    let re = Regex::new("(.*)::h[a-f0-9]{16}")?;
+
+    let reader = ModuleReader::new(module);
+    let mut callgraph = utils::CallGraph::default();
+    let mut line_indexer = LineIndexer::default();
+    for (offset, label) in reader.vtable_slots() {
+        callgraph
+            .vtable_slots
+            .entry(offset)
+            .or_default()
+            .insert(label);
+    }
+    callgraph.address_taken = reader.address_taken();
+
+    let mut call_site_count = 0;
+    for fun in &module.functions {
+        let dem_fun = demangle(&fun.name).to_string();
+        let short_fun = {
+            let simplified = utils::simplify_trait_paths(&dem_fun.clone());
+            re.captures(&simplified)
+                .map_or_else(|| simplified.clone(), |caps| caps[1].to_string())
+        };
+        callgraph
+            .short_label_to_labels
+            .entry(short_fun.clone())
+            .or_default()
+            .insert(dem_fun.clone());
+        for bb in &fun.basic_blocks {
+            for debugloc in bb
+                .instrs
+                .iter()
+                .filter_map(|instr| instr.get_debug_loc().as_ref())
+                .chain(bb.term.get_debug_loc().as_ref())
+            {
+                line_indexer.index(&dem_fun, debugloc);
+            }
+        }
+
+        let mut intrinsics: BTreeMap<String, u32> = BTreeMap::new();
+        for call_site in reader.call_sites(fun) {
+            call_site_count += 1;
+            match call_site.callee {
+                Callee::Function(name) => {
+                    let dem_called = demangle(name).to_string();
+                    let sites = CallSites::one(call_site.in_loop, call_site.debugloc);
+                    callgraph.add_calls(&dem_fun, &dem_called, CallKind::Direct, &sites);
+                }
+                Callee::Intrinsic(name) => *intrinsics.entry(name.to_string()).or_default() += 1,
+                Callee::Indirect {
+                    vtable_offset,
+                    signature,
+                } => {
+                    let call = IndirectCall {
+                        caller: dem_fun.clone(),
+                        vtable_offset,
+                        signature,
+                    };
+                    let sites = CallSites::one(call_site.in_loop, call_site.debugloc);
+                    callgraph
+                        .indirect_calls
+                        .entry(call)
+                        .or_default()
+                        .extend(&sites);
+                }
+                Callee::Unknown => {}
+            }
+        }
+
+        let label_info = callgraph
+            .label_to_label_info
+            .entry(dem_fun.clone())
+            .or_default();
+        label_info.label = Some(dem_fun.clone());
+        label_info.short_label = Some(short_fun);
+        label_info.defined_in = fun
+            .debugloc
+            .as_ref()
+            .and_then(|debugloc| line_indexer.canonical_path(debugloc));
+        label_info.debugloc.clone_from(&fun.debugloc);
+        label_info.signature = Some(module.type_of(fun).to_string());
+        label_info.intrinsics = intrinsics;
+        label_info.targets.extend(target.map(str::to_string));
+    }
+    log::debug!(
+        "{} functions, {call_site_count} call sites, {} of them through function pointers",
+        module.functions.len(),
+        callgraph
+            .indirect_calls
+            .values()
+            .map(|sites| sites.count)
+            .sum::<u32>()
+    );
+    callgraph.file_to_line_to_labels = line_indexer.file_to_line_to_labels;
+    Ok(callgraph)
+}
+
+fn parse_ir_file(ir_path: &Path, target: Option<&str>) -> anyhow::Result<utils::CallGraph> {
+    let module = Module::from_bc_path(ir_path).map_err(|s| anyhow::anyhow!(s))?;
+    parse_module(&module, target)
+}
+
+/// Loads the bitcode of every unit of the build and merges it into a single whole-program
+/// callgraph, so that calls made entirely inside dependencies are traced too.
+#[allow(clippy::missing_errors_doc)]
+pub fn gen_callgraph(units: &[BitcodeUnit]) -> anyhow::Result<utils::CallGraph> {
+    let mut found_root = false;
+    let mut callgraph = utils::CallGraph::default();
+    for unit in units {
+        if !unit.path.exists() {
+            // Build scripts and proc macros of dependencies are not always compiled with our
+            // flags, so they may not have bitcode
+            log::debug!("no bitcode at {}", unit.path.display());
+            continue;
+        }
+        found_root |= unit.target.is_some();
+        log::debug!("loading bitcode {}", unit.path.display());
+        let module_callgraph = parse_ir_file(&unit.path, unit.target.as_deref())
+            .with_context(|| format!("Failed to parse bitcode {}", unit.path.display()))?;
+        callgraph.extend(module_callgraph);
+    }
+    if !found_root {
+        return Err(anyhow!("could not find bytecode file"));
+    }
+    callgraph.resolve_indirect_calls();
+    Ok(callgraph)
+}
+
+/// How unsafe sources are counted towards badness
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
+pub enum Weighting {
+    /// Every unsafe site counts, so a function with ten `unsafe` blocks weighs ten times as much
+    /// as a function with one
+    PerSite,
+    /// Every function containing unsafe code counts once, however many unsafe sites it has
+    PerFunction,
+    /// Every unsafe site counts as the sum of the weights of the categories of its operations,
+    /// so that an `asm!` block can count more than a `get_unchecked`. Categories without a weight
+    /// count 1, and sites without any known operation count once.
+    PerOperation(BTreeMap<OpCategory, u32>),
+}
+
+impl Weighting {
+    /// How much the unsafe sites of a function count towards badness, per crate the sites are
+    /// in. With `PerFunction`, the function is blamed on the crate of its first site.
+    fn weights(&self, sites: &[&UnsafeSite], matches: &SiteMatches) -> BTreeMap<String, u32> {
+        let mut weights: BTreeMap<String, u32> = BTreeMap::new();
+        match self {
+            Self::PerSite => {
+                for site in sites {
+                    *weights.entry(matches.crate_key(site)).or_insert(0) += 1;
+                }
+            }
+            Self::PerFunction => {
+                if let Some(site) = sites.first() {
+                    weights.insert(matches.crate_key(site), 1);
+                }
+            }
+            Self::PerOperation(op_weights) => {
+                for site in sites {
+                    let weight = if site.operations.is_empty() {
+                        1
+                    } else {
+                        site.operations
+                            .iter()
+                            .map(|op| op_weights.get(&op.category).copied().unwrap_or(1))
+                            .fold(0, u32::saturating_add)
+                    };
+                    let total = weights.entry(matches.crate_key(site)).or_insert(0);
+                    *total = total.saturating_add(weight);
+                }
+            }
+        }
+        weights
+    }
+}
+
+/// How unsafe sites are found in the callgraph
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchStrategy {
+    /// Match each site to the functions that have instructions inside its span, according to
+    /// debug info. Sites in files without any debug info are matched by name.
+    DebugInfo,
+    /// Match each site to the functions whose demangled name is the path of the item
+    /// enclosing it
+    Name,
+}
+
+/// The functions each unsafe site was found in
+#[derive(Default)]
+pub struct SiteMatches<'s> {
+    /// Maps the label of every function containing unsafe code to the sites it contains
+    pub label_to_sites: HashMap<String, Vec<&'s UnsafeSite>>,
+    /// How many sites were matched by debug info
+    pub by_debuginfo: usize,
+    /// How many sites were matched by name
+    pub by_name: usize,
+    /// The sites that could not be found in the callgraph
+    pub unmatched: Vec<&'s UnsafeSite>,
+    /// The crates with sites in more than one package, usually because several versions of the
+    /// same crate are linked
+    pub ambiguous_crates: BTreeSet<String>,
+}
+
+impl SiteMatches<'_> {
+    /// Names the crate `site` is in, adding the package version if the crate name alone is
+    /// ambiguous, as in `smallvec@0.6.14`
+    #[must_use]
+    pub fn crate_key(&self, site: &UnsafeSite) -> String {
+        if self.ambiguous_crates.contains(&site.crate_name) {
+            format!("{}@{}", site.crate_name, site.package_version)
+        } else {
+            site.crate_name.clone()
+        }
+    }
+}
+
+/// Finds the crates whose sites come from more than one package
+fn ambiguous_crates(unsafe_sites: &[UnsafeSite]) -> BTreeSet<String> {
+    let mut packages: HashMap<&str, HashSet<(&str, &str)>> = HashMap::new();
+    for site in unsafe_sites {
+        packages
+            .entry(&site.crate_name)
+            .or_default()
+            .insert((&site.package_name, &site.package_version));
+    }
+    packages
+        .into_iter()
+        .filter(|(_, packages)| packages.len() > 1)
+        .map(|(crate_name, _)| crate_name.to_string())
+        .collect()
+}
+
+/// Finds the functions with instructions inside the span of `site`. Returns `None` if there is
+/// no debug info at all for the file the site is in.
+///
+/// Code inlined into a function keeps the lines of the function it comes from, so the function
+/// it is inlined into has instructions inside the span too. `llvm_ir` does not tell where a
+/// location is inlined at, so the innermost scope of the site is found from where the functions
+/// are defined instead: if some are defined in the site's file around the site, only those are
+/// kept.
+fn labels_by_debuginfo<'a>(
+    callgraph: &'a utils::CallGraph,
+    site: &UnsafeSite,
+) -> Option<HashSet<&'a str>> {
+    let line_to_labels = callgraph.file_to_line_to_labels.get(&site.file)?;
+    let labels: HashSet<&str> = (site.span.start_line..=site.span.end_line)
+        .filter_map(|line| u32::try_from(line).ok())
+        .filter_map(|line| line_to_labels.get(&line))
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    let defined_at: HashMap<&str, usize> = labels
+        .iter()
+        .filter_map(|label| {
+            let label_info = callgraph.label_to_label_info.get(*label)?;
+            let line = usize::try_from(label_info.debugloc.as_ref()?.line).ok()?;
+            (label_info.defined_in.as_ref()? == &site.file).then_some((*label, line))
+        })
+        .collect();
+    // A block is in the function defined last before it, while an unsafe function is the first
+    // defined inside its span, which starts at its attributes and is followed by its closures
+    let scope = if site.kind == UnsafeKind::Block {
+        defined_at
+            .values()
+            .filter(|line| **line <= site.span.start_line)
+            .max()
+    } else {
+        defined_at
+            .values()
+            .filter(|line| (site.span.start_line..=site.span.end_line).contains(*line))
+            .min()
+    };
+    Some(match scope {
+        Some(scope) => labels
+            .into_iter()
+            .filter(|label| defined_at.get(label) == Some(scope))
+            .collect(),
+        None => labels,
+    })
+}
+
+/// Finds the functions named like the item enclosing `site`, or, for a site in the default body
+/// of a trait method, the copies of that method of every implementor. When several versions of a
+/// crate are linked, their functions have the same names but different hashes, so functions that
+/// debug info shows to be defined in another file than the site are left out.
+fn labels_by_name<'a>(callgraph: &'a utils::CallGraph, site: &UnsafeSite) -> HashSet<&'a str> {
+    let labels: Vec<&HashSet<String>> = site.default_method.as_ref().map_or_else(
+        || {
+            callgraph
+                .short_label_to_labels
+                .get(&utils::simplify_trait_paths(&site.item))
+                .into_iter()
+                .collect()
+        },
+        |default_method| {
+            callgraph
+                .short_label_to_labels
+                .iter()
+                .filter(|(short_label, _)| default_method.is_copied_to(short_label))
+                .map(|(_, labels)| labels)
+                .collect()
+        },
+    );
+    labels
+        .into_iter()
+        .flatten()
+        .filter(|label| {
+            callgraph
+                .label_to_label_info
+                .get(*label)
+                .and_then(|label_info| label_info.defined_in.as_ref())
+                .is_none_or(|file| *file == site.file)
+        })
+        .map(String::as_str)
+        .collect()
+}
+
+/// Finds the callgraph labels of the functions containing each of the unsafe sites
+#[must_use]
+pub fn match_unsafe_sites<'s>(
+    callgraph: &utils::CallGraph,
+    unsafe_sites: &'s [UnsafeSite],
+    strategy: MatchStrategy,
+) -> SiteMatches<'s> {
+    let mut matches = SiteMatches {
+        ambiguous_crates: ambiguous_crates(unsafe_sites),
+        ..SiteMatches::default()
+    };
+    for site in unsafe_sites {
+        // Debug info would attribute the code inside an `unsafe impl` to the impl itself
+        if !site.kind.has_code() {
+            matches.unmatched.push(site);
+            continue;
+        }
+        let by_debuginfo = match strategy {
+            MatchStrategy::DebugInfo => labels_by_debuginfo(callgraph, site),
+            MatchStrategy::Name => None,
+        };
+        let labels = if let Some(labels) = by_debuginfo {
+            if !labels.is_empty() {
+                matches.by_debuginfo += 1;
+            }
+            labels
+        } else {
+            let labels = labels_by_name(callgraph, site);
+            if !labels.is_empty() {
+                matches.by_name += 1;
+            }
+            labels
+        };
+        if labels.is_empty() {
+            matches.unmatched.push(site);
+        }
+        for label in labels {
+            matches
+                .label_to_sites
+                .entry(label.to_string())
+                .or_default()
+                .push(site);
+        }
+    }
+    matches
+}
+
+/// The probable reason why an unsafe site was not found in the callgraph
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum UnmatchedCause {
+    /// The file the site is in was not used by the build, e.g. because it is only included
+    /// under a `cfg` that is not enabled, or the crate is an inactive optional dependency
+    NotCompiled,
+    /// The file was compiled, but no code was generated for the site. It is probably in a
+    /// generic function that is never instantiated, or in code that is `cfg`'d out.
+    NotInstantiated,
+    /// Code was generated for the site (or there is no debug info to tell), but no function
+    /// with the name reconstructed from its path was found
+    NameMismatch,
+    /// The site has no code of its own: it is a declaration, like an `unsafe impl` or an
+    /// `unsafe fn` declared in a trait without a body, or it is evaluated at compile time
+    NoCode,
+}
+
+/// Guesses why `site` could not be matched to any function in the callgraph. `compiled_files`
+/// holds the canonicalized paths of every file used by the build.
+#[must_use]
+pub fn unmatched_cause<S: BuildHasher>(
+    callgraph: &utils::CallGraph,
+    site: &UnsafeSite,
+    compiled_files: &HashSet<PathBuf, S>,
+) -> UnmatchedCause {
+    if !site.kind.has_code() {
+        return UnmatchedCause::NoCode;
+    }
+    if !compiled_files.contains(&site.file) {
+        return UnmatchedCause::NotCompiled;
+    }
+    match labels_by_debuginfo(callgraph, site) {
+        Some(labels) if labels.is_empty() => UnmatchedCause::NotInstantiated,
+        _ => UnmatchedCause::NameMismatch,
+    }
+}
+
+/// Finds `label` and every function that calls it, directly or through other functions, using
+/// only the calls `follow` accepts
+fn transitive_callers<'a>(
+    callgraph: &'a utils::CallGraph,
+    label: &'a str,
+    follow: impl Fn(CallKind) -> bool,
+) -> HashSet<&'a str> {
+    let mut queued_to_traverse: Vec<&str> = vec![label];
+    let mut callers: HashSet<&str> = HashSet::from([label]);
+    while let Some(current_node) = queued_to_traverse.pop() {
+        if let Some(label_info) = callgraph.label_to_label_info.get(current_node) {
+            for (caller_node, edge) in &label_info.caller_labels {
+                if follow(edge.kind) && callers.insert(caller_node) {
+                    queued_to_traverse.push(caller_node);
+                }
+            }
+        }
+    }
+    callers
+}
+
+/// How the calls leading to unsafe code count towards badness
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallWeighting {
+    /// A function counts the unsafe code it can reach once, however it reaches it
+    Reachability,
+    /// A function counts the unsafe code it can reach once per call site of its own leading
+    /// there, and `loop_weight` times per such call site inside a loop
+    CallSites { loop_weight: u32 },
+}
+
+impl CallWeighting {
+    /// How many times `label` counts the unsafe code in `source`, given every function
+    /// `tainted_by` that can reach `source`
+    fn multiplier(
+        self,
+        callee_labels: &HashMap<&str, Vec<(&str, &CallEdge)>>,
+        label: &str,
+        source: &str,
+        tainted_by: &HashSet<&str>,
+    ) -> u32 {
+        match self {
+            Self::CallSites { loop_weight } if label != source => callee_labels
+                .get(label)
+                .into_iter()
+                .flatten()
+                .filter(|(callee, _)| tainted_by.contains(callee))
+                .map(|(_, edge)| edge.sites.weight(loop_weight))
+                .fold(0, u32::saturating_add),
+            _ => 1,
+        }
+    }
+}
+
+/// Computes the badness of every function of the given root targets
+#[must_use]
+pub fn trace_unsafety(
+    callgraph: &utils::CallGraph,
+    targets: &[RootTarget],
+    matches: &SiteMatches,
+    weighting: &Weighting,
+    call_weighting: CallWeighting,
+) -> BadnessMap {
+    let root_targets = RootTargets::new(targets);
+    let callee_labels = callee_labels(callgraph);
+    let mut label_to_badness: HashMap<String, Badness> = HashMap::new();
+    for (tainted_function, sites) in &matches.label_to_sites {
+        let weights = weighting.weights(sites, matches);
+        let tainted_by = transitive_callers(callgraph, tainted_function, |_| true);
+        let directly_tainted_by =
+            transitive_callers(callgraph, tainted_function, |kind| !kind.is_indirect());
+
+        for tainted_by_node_id in &tainted_by {
+            if let Some(label_info) = callgraph.label_to_label_info.get(*tainted_by_node_id) {
+                let targets = root_targets.of(label_info);
+                if targets.is_empty() {
+                    continue;
+                }
+                if let Some(shortlabel) = &label_info.short_label {
+                    let badness = label_to_badness
+                        .entry(shortlabel.clone())
+                        .or_insert_with(|| Badness::new(label_info));
+                    badness
+                        .targets
+                        .extend(targets.into_iter().map(str::to_string));
+                    let multiplier = call_weighting.multiplier(
+                        &callee_labels,
+                        tainted_by_node_id,
+                        tainted_function,
+                        &tainted_by,
+                    );
+                    for (crate_name, weight) in &weights {
+                        let weight = weight.saturating_mul(multiplier);
+                        badness.add(crate_name, weight);
+                        if !directly_tainted_by.contains(tainted_by_node_id) {
+                            badness.indirect = badness.indirect.saturating_add(weight);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut ret_badness = BadnessMap::new();
+    // To print this out, we have to dedup all the node labels, since multiple nodes can have the same label
+    for (label, badness) in &label_to_badness {
+        let ret = ret_badness
+            .entry(utils::simplify_trait_paths(label))
+            .or_insert_with(|| Badness::new(&badness.label_info));
+        for (crate_name, weight) in &badness.by_crate {
+            ret.add(crate_name, *weight);
+        }
+        ret.indirect = ret.indirect.saturating_add(badness.indirect);
+        ret.targets.extend(badness.targets.iter().cloned());
+    }
+    ret_badness
+}
+
+/// Matches the labels of functions in the given crate
+#[allow(clippy::unwrap_used)]
+fn crate_regex(crate_name: &str) -> Regex {
+    Regex::new(&format!(r"^<*{}::", str::replace(crate_name, "-", "_"))).unwrap()
+}
+
+/// The callgraph only records callers, so this inverts it to walk down from a function
+fn callee_labels(callgraph: &utils::CallGraph) -> HashMap<&str, Vec<(&str, &CallEdge)>> {
+    let mut callee_labels: HashMap<&str, Vec<(&str, &CallEdge)>> = HashMap::new();
+    for (label, label_info) in &callgraph.label_to_label_info {
+        for (caller, edge) in &label_info.caller_labels {
+            callee_labels
+                .entry(caller.as_str())
+                .or_default()
+                .push((label.as_str(), edge));
+        }
+    }
+    callee_labels
+}
+
+/// Finds the labels of every function that can be reached from a function of the root targets,
+/// including the root targets' functions themselves
+#[must_use]
+pub fn reachable_labels<'a>(
+    callgraph: &'a utils::CallGraph,
+    targets: &[RootTarget],
+) -> HashSet<&'a str> {
+    let root_targets = RootTargets::new(targets);
+    let callee_labels = callee_labels(callgraph);
+    let mut queued_to_traverse: Vec<&str> = callgraph
+        .label_to_label_info
+        .iter()
+        .filter(|(_, label_info)| !root_targets.of(label_info).is_empty())
+        .map(|(label, _)| label.as_str())
+        .collect();
+    let mut reachable: HashSet<&str> = queued_to_traverse.iter().copied().collect();
+    while let Some(current_node) = queued_to_traverse.pop() {
+        for (callee, _) in callee_labels
+            .get(current_node)
+            .into_iter()
+            .flatten()
+            .copied()
+        {
+            if reachable.insert(callee) {
+                queued_to_traverse.push(callee);
+            }
+        }
+    }
+    reachable
+}
+
+/// An unsafe source that contributes to the badness of an explained function
+pub struct Explanation<'s> {
+    /// The label of the tainted function
+    pub source: String,
+    /// The unsafe sites `source` contains
+    pub sites: Vec<&'s UnsafeSite>,
+    /// The shortest call chain from the explained function down to `source`. The first label is
+    /// an instantiation of the explained function and the last one is `source`.
+    pub chain: Vec<String>,
+    /// How and where each function in `chain` but the first is called by the one before it
+    pub calls: Vec<CallEdge>,
+}
+
+/// Lists every unsafe source counted in the badness of `function`, with the shortest call chain
+/// leading to it.
+///
+/// `function` is a short label, as printed by `trace_unsafety`. Returns `None` if the function
+/// is not in the callgraph.
+#[must_use]
+pub fn explain_unsafety<'s>(
+    callgraph: &utils::CallGraph,
+    function: &str,
+    matches: &SiteMatches<'s>,
+) -> Option<Vec<Explanation<'s>>> {
+    let start_labels = callgraph
+        .short_label_to_labels
+        .get(&utils::simplify_trait_paths(function))?;
+
+    let callee_labels = callee_labels(callgraph);
+
+    // Breadth-first, so the first path found to each node is a shortest one
+    let mut parent: HashMap<&str, Option<(&str, &CallEdge)>> = HashMap::new();
+    let mut queued_to_traverse: VecDeque<&str> = VecDeque::new();
+    for label in start_labels {
+        parent.insert(label.as_str(), None);
+        queued_to_traverse.push_back(label.as_str());
+    }
+    let mut explanations = Vec::new();
+    while let Some(current_node) = queued_to_traverse.pop_front() {
+        if let Some(sites) = matches.label_to_sites.get(current_node) {
+            let mut chain = vec![current_node.to_string()];
+            let mut calls = Vec::new();
+            let mut hop = current_node;
+            while let Some(Some((caller, edge))) = parent.get(hop) {
+                chain.push((*caller).to_string());
+                calls.push((*edge).clone());
+                hop = *caller;
+            }
+            chain.reverse();
+            calls.reverse();
+            explanations.push(Explanation {
+                source: current_node.to_string(),
+                sites: sites.clone(),
+                chain,
+                calls,
+            });
+        }
+        for (callee, edge) in callee_labels.get(current_node).into_iter().flatten() {
+            if !parent.contains_key(callee) {
+                parent.insert(*callee, Some((current_node, *edge)));
+                queued_to_traverse.push_back(*callee);
+            }
+        }
+    }
+    Some(explanations)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::unsafe_site::{DefaultMethod, SourceSpan, UnsafeOperation};
+    use crate::utils::CallGraph;
+
+    /// One unsafe block in each of the given functions
+    fn sites(items: &[&str]) -> Vec<UnsafeSite> {
+        items
+            .iter()
+            .map(|item| UnsafeSite {
+                item: (*item).to_string(),
+                kind: UnsafeKind::Block,
+                file: PathBuf::from("src/lib.rs"),
+                span: SourceSpan::default(),
+                crate_name: item.split("::").next().unwrap().to_string(),
+                package_name: item.split("::").next().unwrap().to_string(),
+                package_version: "0.1.0".to_string(),
+                default_method: None,
+                operations: Vec::new(),
+            })
+            .collect()
+    }
+
+    /// The library target of the crate `crate_name`
+    fn lib(crate_name: &str) -> Vec<RootTarget> {
+        vec![RootTarget {
+            crate_name: crate_name.to_string(),
+            label: "lib".to_string(),
+        }]
+    }
+
+    /// Builds a callgraph from `(caller, callee)` edges between functions whose labels are their
+    /// short labels
+    fn callgraph(edges: &[(&str, &str)]) -> CallGraph {
+        let mut callgraph = CallGraph::default();
+        for (caller, callee) in edges {
+            for label in [caller, callee] {
+                let label_info = callgraph
+                    .label_to_label_info
+                    .entry((*label).to_string())
+                    .or_default();
+                label_info.short_label = Some((*label).to_string());
+                callgraph
+                    .short_label_to_labels
+                    .entry((*label).to_string())
+                    .or_default()
+                    .insert((*label).to_string());
+            }
+            callgraph.add_call(caller, callee, CallKind::Direct);
+        }
+        callgraph
+    }
+
+    #[test]
+    fn test_parse_module() {
+        let module = ir::module_from_ir(
+            r"
+            declare void @llvm.donothing()
+            declare void @dep()
+
+            define void @callback() {
+              call void @dep()
+              ret void
+            }
+
+            define void @root() {
+              call void @dep()
+              call void @dep()
+              call void @llvm.donothing()
+              call void @register(ptr @callback)
+              ret void
+            }
+
+            define void @register(ptr %f) {
+              call void %f()
+              ret void
+            }
+            ",
+        );
+        let mut callgraph = parse_module(&module, Some("lib")).unwrap();
+        callgraph.resolve_indirect_calls();
+
+        let dep_callers = &callgraph.label_to_label_info["dep"].caller_labels;
+        assert_eq!(dep_callers["root"].sites.count, 2);
+        assert_eq!(dep_callers["callback"].sites.count, 1);
+        assert!(!callgraph.label_to_label_info.contains_key("llvm.donothing"));
+        assert_eq!(
+            callgraph.label_to_label_info["root"].intrinsics["llvm.donothing"],
+            1
+        );
+        assert_eq!(
+            callgraph.label_to_label_info["callback"].caller_labels["register"].kind,
+            CallKind::AddressTaken
+        );
+        assert!(callgraph.label_to_label_info["root"]
+            .targets
+            .contains("lib"));
+        assert!(callgraph.label_to_label_info["dep"].targets.is_empty());
+    }
+
+    #[test]
+    fn test_explain_finds_shortest_chains() {
+        let callgraph = callgraph(&[
+            ("root::f", "root::g"),
+            ("root::g", "dep::h"),
+            ("root::f", "dep::h"),
+            ("root::g", "dep::i"),
+        ]);
+        let tainted = sites(&["dep::h", "dep::i"]);
+        let matches = match_unsafe_sites(&callgraph, &tainted, MatchStrategy::DebugInfo);
+        let mut explanations = explain_unsafety(&callgraph, "root::f", &matches).unwrap();
+        explanations.sort_by(|a, b| a.source.cmp(&b.source));
+
+        assert_eq!(explanations.len(), 2);
+        assert_eq!(explanations[0].source, "dep::h");
+        assert_eq!(explanations[0].sites.len(), 1);
+        assert_eq!(explanations[0].chain, vec!["root::f", "dep::h"]);
+        assert_eq!(explanations[1].source, "dep::i");
+        assert_eq!(explanations[1].chain, vec!["root::f", "root::g", "dep::i"]);
+
+        // Every source the explanation lists is counted in the badness, and vice versa
+        let badness = trace_unsafety(
+            &callgraph,
+            &lib("root"),
+            &matches,
+            &Weighting::PerSite,
+            CallWeighting::Reachability,
+        );
+        assert_eq!(badness["root::f"].total, 2);
+    }
+
+    #[test]
+    fn test_weighting() {
+        let callgraph = callgraph(&[("root::f", "dep::h"), ("root::f", "dep::i")]);
+        // `dep::h` contains three unsafe sites, `dep::i` one
+        let tainted = sites(&["dep::h", "dep::h", "dep::h", "dep::i"]);
+        let matches = match_unsafe_sites(&callgraph, &tainted, MatchStrategy::Name);
+
+        let badness = trace_unsafety(
+            &callgraph,
+            &lib("root"),
+            &matches,
+            &Weighting::PerSite,
+            CallWeighting::Reachability,
+        );
+        assert_eq!(badness["root::f"].total, 4);
+
+        let badness = trace_unsafety(
+            &callgraph,
+            &lib("root"),
+            &matches,
+            &Weighting::PerFunction,
+            CallWeighting::Reachability,
+        );
+        assert_eq!(badness["root::f"].total, 2);
+    }
+
+    #[test]
+    fn test_operation_weighting() {
+        let callgraph = callgraph(&[("root::f", "dep::h"), ("root::f", "dep::i")]);
+        // `dep::h` has an `asm!` block and a block with two unchecked calls, `dep::i` a block
+        // without any known operation
+        let mut tainted = sites(&["dep::h", "dep::h", "dep::i"]);
+        let op = |category| UnsafeOperation {
+            category,
+            span: SourceSpan::default(),
+        };
+        tainted[0].operations = vec![op(OpCategory::InlineAsm)];
+        tainted[1].operations = vec![op(OpCategory::UncheckedCall), op(OpCategory::UncheckedCall)];
+        let matches = match_unsafe_sites(&callgraph, &tainted, MatchStrategy::Name);
+
+        let weighting = Weighting::PerOperation(BTreeMap::from([(OpCategory::InlineAsm, 10)]));
+        let badness = trace_unsafety(
+            &callgraph,
+            &lib("root"),
+            &matches,
+            &weighting,
+            CallWeighting::Reachability,
+        );
+        assert_eq!(badness["root::f"].total, 13);
+    }
+
+    #[test]
+    fn test_call_site_weighting_counts_own_call_sites() {
+        let mut callgraph = callgraph(&[
+            ("root::once", "dep::h"),
+            ("root::twice", "dep::h"),
+            ("root::twice", "dep::h"),
+            ("root::outer", "root::twice"),
+        ]);
+        callgraph.add_calls(
+            "root::hot",
+            "dep::h",
+            CallKind::Direct,
+            &CallSites::one(true, None),
+        );
+        callgraph
+            .label_to_label_info
+            .entry("root::hot".to_string())
+            .or_default()
+            .short_label = Some("root::hot".to_string());
+        let tainted = sites(&["dep::h"]);
+        let matches = match_unsafe_sites(&callgraph, &tainted, MatchStrategy::Name);
+
+        let badness = trace_unsafety(
+            &callgraph,
+            &lib("root"),
+            &matches,
+            &Weighting::PerSite,
+            CallWeighting::CallSites { loop_weight: 10 },
+        );
+        assert_eq!(badness["root::once"].total, 1);
+        assert_eq!(badness["root::twice"].total, 2);
+        assert_eq!(badness["root::outer"].total, 1);
+        assert_eq!(badness["root::hot"].total, 10);
+    }
+
+    #[test]
+    fn test_indirect_badness() {
+        let mut callgraph = callgraph(&[("root::f", "dep::g"), ("root::h", "dep::i")]);
+        callgraph.add_call("root::f", "dep::i", CallKind::Virtual);
+        let tainted = sites(&["dep::g", "dep::i"]);
+        let matches = match_unsafe_sites(&callgraph, &tainted, MatchStrategy::Name);
+        let badness = trace_unsafety(
+            &callgraph,
+            &lib("root"),
+            &matches,
+            &Weighting::PerSite,
+            CallWeighting::Reachability,
+        );
+
+        assert_eq!(badness["root::f"].total, 2);
+        assert_eq!(badness["root::f"].indirect, 1);
+        assert_eq!(badness["root::h"].indirect, 0);
+
+        let explanations = explain_unsafety(&callgraph, "root::f", &matches).unwrap();
+        let to_i = explanations.iter().find(|e| e.source == "dep::i").unwrap();
+        assert_eq!(to_i.calls.len(), 1);
+        assert_eq!(to_i.calls[0].kind, CallKind::Virtual);
+    }
+
+    #[test]
+    fn test_blame_by_crate() {
+        let callgraph = callgraph(&[
+            ("root::f", "root::g"),
+            ("root::g", "bytes::h"),
+            ("root::f", "smallvec::i"),
+        ]);
+        let tainted = sites(&["bytes::h", "bytes::h", "smallvec::i", "root::g"]);
+        let matches = match_unsafe_sites(&callgraph, &tainted, MatchStrategy::Name);
+        let badness = trace_unsafety(
+            &callgraph,
+            &lib("root"),
+            &matches,
+            &Weighting::PerSite,
+            CallWeighting::Reachability,
+        );
+
+        assert_eq!(badness["root::f"].total, 4);
+        assert_eq!(
+            badness["root::f"].by_crate,
+            BTreeMap::from([
+                ("bytes".to_string(), 2),
+                ("root".to_string(), 1),
+                ("smallvec".to_string(), 1)
+            ])
+        );
+        assert_eq!(badness["root::g"].total, 3);
+    }
+
+    #[test]
+    fn test_badness_per_target() {
+        let mut callgraph = callgraph(&[
+            ("root::f", "dep::h"),
+            ("root::main", "root::f"),
+            ("tool::main", "dep::h"),
+        ]);
+        // The library and the `root` binary share their crate name
+        for (label, target) in [
+            ("root::f", "lib"),
+            ("root::f", "bin root"),
+            ("root::main", "bin root"),
+        ] {
+            callgraph
+                .label_to_label_info
+                .get_mut(label)
+                .unwrap()
+                .targets
+                .insert(target.to_string());
+        }
+        let tainted = sites(&["dep::h"]);
+        let matches = match_unsafe_sites(&callgraph, &tainted, MatchStrategy::Name);
+        let mut targets = lib("root");
+        for (crate_name, label) in [("root", "bin root"), ("tool", "bin tool")] {
+            targets.push(RootTarget {
+                crate_name: crate_name.to_string(),
+                label: label.to_string(),
+            });
+        }
+        let badness = trace_unsafety(
+            &callgraph,
+            &targets,
+            &matches,
+            &Weighting::PerSite,
+            CallWeighting::Reachability,
+        );
+
+        assert_eq!(badness.len(), 3);
+        assert_eq!(
+            badness["root::f"].targets,
+            BTreeSet::from(["bin root".to_string(), "lib".to_string()])
+        );
+        assert_eq!(
+            badness["root::main"].targets,
+            BTreeSet::from(["bin root".to_string()])
+        );
+        assert_eq!(
+            badness["tool::main"].targets,
+            BTreeSet::from(["bin tool".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_two_versions_of_a_crate() {
+        // `dep` 0.1.0 and 0.2.0 are both linked, and their `dep::h` only differ in their hash
+        let dir = tempfile::tempdir().unwrap();
+        let mut callgraph = callgraph(&[("root::f", "dep::h::h01"), ("root::g", "dep::h::h02")]);
+        for (label, version) in [("dep::h::h01", "0.1.0"), ("dep::h::h02", "0.2.0")] {
+            let file = dir.path().join(format!("dep-{version}.rs"));
+            std::fs::write(&file, "").unwrap();
+            let label_info = callgraph.label_to_label_info.get_mut(label).unwrap();
+            label_info.short_label = Some("dep::h".to_string());
+            label_info.debugloc = Some(DebugLoc {
+                line: 1,
+                col: None,
+                filename: file.to_string_lossy().to_string(),
+                directory: None,
+            });
+            label_info.defined_in = Some(file.canonicalize().unwrap());
+            callgraph
+                .short_label_to_labels
+                .entry("dep::h".to_string())
+                .or_default()
+                .insert(label.to_string());
+        }
+        // Only `dep::h` of 0.1.0 contains unsafe code
+        let mut tainted = sites(&["dep::h", "dep::i"]);
+        tainted[0].file = dir.path().join("dep-0.1.0.rs").canonicalize().unwrap();
+        tainted[1].package_version = "0.2.0".to_string();
+        let matches = match_unsafe_sites(&callgraph, &tainted, MatchStrategy::Name);
+        assert_eq!(
+            matches.ambiguous_crates,
+            BTreeSet::from(["dep".to_string()])
+        );
+        assert_eq!(
+            matches.label_to_sites.keys().collect::<Vec<_>>(),
+            ["dep::h::h01"]
+        );
+
+        let badness = trace_unsafety(
+            &callgraph,
+            &lib("root"),
+            &matches,
+            &Weighting::PerSite,
+            CallWeighting::Reachability,
+        );
+        assert_eq!(
+            badness["root::f"].by_crate,
+            BTreeMap::from([("dep@0.1.0".to_string(), 1)])
+        );
+        assert!(!badness.contains_key("root::g"));
+    }
+
+    #[test]
+    fn test_default_method_copies() {
+        let callgraph = callgraph(&[
+            ("root::f", "<root::A as Poke>::poke"),
+            ("root::g", "<root::B as Poke<u8>>::poke::{{closure}}"),
+            ("root::h", "<root::C as Other>::poke"),
+            ("root::i", "<root::D as Poke>::poke_more"),
+        ]);
+        // One unsafe block in the default body of `Poke::poke`, and one in a closure inside it
+        let mut tainted = sites(&["root::Poke::poke", "root::Poke::poke::{{closure}}"]);
+        for site in &mut tainted {
+            site.default_method = Some(DefaultMethod {
+                trait_name: "Poke".to_string(),
+                item: site.item["root::Poke::".len()..].to_string(),
+            });
+        }
+        let matches = match_unsafe_sites(&callgraph, &tainted, MatchStrategy::Name);
+        assert!(matches.unmatched.is_empty());
+        assert_eq!(
+            matches.label_to_sites.keys().collect::<BTreeSet<_>>(),
+            BTreeSet::from([
+                &"<root::A as Poke>::poke".to_string(),
+                &"<root::B as Poke<u8>>::poke::{{closure}}".to_string(),
+            ])
+        );
+
+        let badness = trace_unsafety(
+            &callgraph,
+            &lib("root"),
+            &matches,
+            &Weighting::PerSite,
+            CallWeighting::Reachability,
+        );
+        assert_eq!(badness["root::f"].total, 1);
+        assert_eq!(badness["root::g"].total, 1);
+        assert!(!badness.contains_key("root::h"));
+        assert!(!badness.contains_key("root::i"));
+    }
+
+    #[test]
+    fn test_explain_unknown_function() {
+        let callgraph = callgraph(&[("root::f", "dep::h")]);
+        let matches = SiteMatches::default();
+        assert!(explain_unsafety(&callgraph, "root::nope", &matches).is_none());
+    }
+
+    #[test]
+    fn test_match_by_debuginfo() {
+        // `dep::h` is the only instantiation of a generic whose name does not match its path.
+        // `dep::i` is on lines 19 to 22, except for the body of its closure on line 21.
+        let mut callgraph = callgraph(&[("root::f", "dep::h<u8>"), ("root::f", "dep::i")]);
+        let line_to_labels = callgraph
+            .file_to_line_to_labels
+            .entry(PathBuf::from("src/lib.rs"))
+            .or_default();
+        line_to_labels
+            .entry(10)
+            .or_default()
+            .insert("dep::h<u8>".to_string());
+        for line in [19, 20, 22] {
+            line_to_labels
+                .entry(line)
+                .or_default()
+                .insert("dep::i".to_string());
+        }
+        line_to_labels
+            .entry(21)
+            .or_default()
+            .insert("dep::i::{{closure}}".to_string());
+
+        let mut tainted = sites(&["dep::h", "dep::i::{{closure}}", "dep::j"]);
+        tainted[0].span = SourceSpan {
+            start_line: 9,
+            start_column: 5,
+            end_line: 11,
+            end_column: 5,
+        };
+        tainted[1].span = SourceSpan {
+            start_line: 21,
+            start_column: 9,
+            end_line: 21,
+            end_column: 30,
+        };
+        // `dep::j` was never compiled, so the lines it spans have no code
+        tainted[2].span = SourceSpan {
+            start_line: 30,
+            start_column: 1,
+            end_line: 32,
+            end_column: 1,
+        };
+
+        let matches = match_unsafe_sites(&callgraph, &tainted, MatchStrategy::DebugInfo);
+        assert_eq!(matches.by_debuginfo, 2);
+        assert_eq!(matches.by_name, 0);
+        assert_eq!(matches.unmatched.len(), 1);
+        assert!(matches.label_to_sites.contains_key("dep::h<u8>"));
+        assert!(matches.label_to_sites.contains_key("dep::i::{{closure}}"));
+        assert!(!matches.label_to_sites.contains_key("dep::i"));
+
+        // Name matching only finds sites whose path is the name of the function
+        let matches = match_unsafe_sites(&callgraph, &tainted, MatchStrategy::Name);
+        assert_eq!(matches.by_debuginfo, 0);
+        assert_eq!(matches.by_name, 0);
+        assert_eq!(matches.unmatched.len(), 3);
+    }
+
+    #[test]
+    fn test_reachable_labels() {
+        let callgraph = callgraph(&[
+            ("root::f", "dep::g"),
+            ("dep::g", "other::h"),
+            ("dep::unused", "other::i"),
+        ]);
+        let reachable = reachable_labels(&callgraph, &lib("root"));
+        assert_eq!(reachable, HashSet::from(["root::f", "dep::g", "other::h"]));
+    }
+
+    #[test]
+    fn test_unmatched_cause() {
+        let mut callgraph = callgraph(&[("root::f", "dep::h")]);
+        callgraph
+            .file_to_line_to_labels
+            .entry(PathBuf::from("src/lib.rs"))
+            .or_default()
+            .entry(10)
+            .or_default()
+            .insert("dep::h".to_string());
+        let mut tainted = sites(&["dep::h", "dep::i", "dep::j"]);
+        tainted[0].span.start_line = 10;
+        tainted[0].span.end_line = 10;
+        tainted[1].span.start_line = 20;
+        tainted[1].span.end_line = 21;
+        tainted[2].file = PathBuf::from("src/windows.rs");
+        let compiled_files = HashSet::from([PathBuf::from("src/lib.rs")]);
+
+        // There is code on the line of `dep::h`, so only its name can be to blame
+        assert_eq!(
+            unmatched_cause(&callgraph, &tainted[0], &compiled_files),
+            UnmatchedCause::NameMismatch
+        );
+        assert_eq!(
+            unmatched_cause(&callgraph, &tainted[1], &compiled_files),
+            UnmatchedCause::NotInstantiated
+        );
+        assert_eq!(
+            unmatched_cause(&callgraph, &tainted[2], &compiled_files),
+            UnmatchedCause::NotCompiled
+        );
+        tainted[1].kind = UnsafeKind::TraitFn;
+        assert_eq!(
+            unmatched_cause(&callgraph, &tainted[1], &compiled_files),
+            UnmatchedCause::NoCode
+        );
+    }
+
+    #[test]
+    fn test_inlined_code() {
+        // `dep::inner`, defined on line 3, is inlined into `dep::outer`, defined on line 1, so both
+        // have code on the lines of `dep::inner`. So does the closure defined on line 5.
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("dep.rs");
+        std::fs::write(&file, "").unwrap();
+        let file = file.canonicalize().unwrap();
+        let mut callgraph = callgraph(&[("dep::outer", "dep::inner")]);
+        for (label, line) in [
+            ("dep::outer", 1),
+            ("dep::inner", 3),
+            ("dep::inner::{{closure}}", 5),
+        ] {
+            let label_info = callgraph
+                .label_to_label_info
+                .entry(label.to_string())
+                .or_default();
+            label_info.debugloc = Some(DebugLoc {
+                line,
+                col: None,
+                filename: file.to_string_lossy().to_string(),
+                directory: None,
+            });
+            label_info.defined_in = Some(file.clone());
+        }
+        let line_to_labels = callgraph
+            .file_to_line_to_labels
+            .entry(file.clone())
+            .or_default();
+        for line in [4, 5, 6] {
+            line_to_labels
+                .entry(line)
+                .or_default()
+                .extend(["dep::outer".to_string(), "dep::inner".to_string()]);
+        }
+        line_to_labels
+            .entry(6)
+            .or_default()
+            .insert("dep::inner::{{closure}}".to_string());
+
+        let mut site = sites(&["dep::inner"]).remove(0);
+        site.file = file;
+        site.span = SourceSpan {
+            start_line: 4,
+            start_column: 5,
+            end_line: 6,
+            end_column: 5,
+        };
+        assert_eq!(
+            labels_by_debuginfo(&callgraph, &site),
+            Some(HashSet::from(["dep::inner"]))
+        );
+        // All of `dep::inner` is unsafe, from its attributes on line 2
+        site.kind = UnsafeKind::Function;
+        site.span.start_line = 2;
+        assert_eq!(
+            labels_by_debuginfo(&callgraph, &site),
+            Some(HashSet::from(["dep::inner"]))
+        );
+
+        // Without knowing where the functions are defined, all of them are kept
+        for label_info in callgraph.label_to_label_info.values_mut() {
+            label_info.debugloc = None;
+        }
+        assert_eq!(
+            labels_by_debuginfo(&callgraph, &site),
+            Some(HashSet::from([
+                "dep::outer",
+                "dep::inner",
+                "dep::inner::{{closure}}"
+            ]))
+        );
+    }
+}
@@ -3,6 +3,16 @@
 #![deny(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
 
 mod callgraph_gen;
+mod unsafe_site;
 mod utils;
-pub use callgraph_gen::{gen_callgraph, trace_unsafety};
-pub use utils::{configure_rustup_toolchain, simplify_trait_paths, CallGraph};
+pub use callgraph_gen::{
+    explain_unsafety, gen_callgraph, match_unsafe_sites, reachable_labels, trace_unsafety,
+    unmatched_cause, Badness, BadnessMap, BitcodeUnit, CallWeighting, Explanation, MatchStrategy,
+    RootTarget, SiteMatches, UnmatchedCause, Weighting,
+};
+pub use unsafe_site::{
+    DefaultMethod, OpCategory, SourceSpan, UnsafeKind, UnsafeOperation, UnsafeSite,
+};
+pub use utils::{
+    configure_rustup_toolchain, simplify_trait_paths, CallEdge, CallGraph, CallKind, CallSites,
+};
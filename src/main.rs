@@ -5,14 +5,15 @@ extern crate log;
 
 mod callgraph_gen;
 mod mark_source;
+mod report;
+mod targets;
 mod trawl_source;
+mod unsafe_site;
 mod utils;
 
-use std::collections::HashMap;
-
 use anyhow::{anyhow, bail};
 use cargo::{
-    core::{Package, Workspace},
+    core::{compiler::CompileMode, Package, Workspace},
     util::Filesystem,
 };
 use structopt::{clap, StructOpt};
@@ -30,14 +31,53 @@ pub struct Args {
     package: Option<String>,
 
     #[structopt(long = "include-tests")]
-    /// Count unsafe usage in tests.
+    /// Build the selected targets as tests, like `cargo test`, and count unsafe usage in tests
     include_tests: bool,
 
+    #[structopt(long = "per-function")]
+    /// Count every unsafe function once instead of counting each of its unsafe sites
+    per_function: bool,
+
+    #[structopt(
+        long = "op-weight",
+        value_name = "CATEGORY=WEIGHT",
+        number_of_values = 1,
+        parse(try_from_str = parse_op_weight),
+        conflicts_with = "per-function"
+    )]
+    /// Count every unsafe site by the operations in it, an operation of CATEGORY counting WEIGHT
+    /// and others 1. May be given several times. CATEGORY is one of deref, unknown-deref,
+    /// unsafe-call, ffi-call, transmute, static-mut, union-field, asm and unchecked
+    op_weights: Vec<(unsafe_site::OpCategory, u32)>,
+
+    #[structopt(long = "weight-by-calls")]
+    /// Count unsafe code once per call site of each function leading to it, instead of once per
+    /// function. Only a function's own call sites count, not those of its callers
+    weight_by_calls: bool,
+
+    #[structopt(long = "loop-weight", value_name = "FACTOR", default_value = "10")]
+    /// With --weight-by-calls, how many times a call site inside a loop counts
+    loop_weight: u32,
+
+    #[structopt(long = "match-by-name")]
+    /// Find unsafe code in the callgraph by function name only, ignoring debug info
+    match_by_name: bool,
+
+    #[structopt(long = "explain", value_name = "FUNCTION")]
+    /// Instead of ranking functions, print the unsafe code FUNCTION reaches and how
+    explain: Option<String>,
+
+    #[structopt(flatten)]
+    target_opts: targets::TargetOpts,
+
+    #[structopt(flatten)]
+    report_opts: report::ReportOpts,
+
     #[structopt(flatten)]
     mark_opts: mark_source::MarkOpts,
 }
 
-fn real_main(args: &Args) -> anyhow::Result<HashMap<String, (u32, utils::LabelInfo)>> {
+fn real_main(args: &Args) -> anyhow::Result<()> {
     let config = cargo::Config::default()?;
     let workspace_root = cargo::util::important_paths::find_root_manifest_for_wd(config.cwd())?;
     let ws = cargo::core::Workspace::new(&workspace_root, &config)?;
@@ -71,13 +111,86 @@ fn real_main(args: &Args) -> anyhow::Result<HashMap<String, (u32, utils::LabelIn
 
     // smoelius: `trawl_source::get_tainted` must be called before `callgraph_gen::gen_callgraph`
     // because `get_tainted` performs the build.
-    let tainted = trawl_source::get_tainted(&config, &ws, &args.package, args.include_tests)?;
-    let callgraph = callgraph_gen::gen_callgraph(&ws, &crate_name)?;
-    Ok(callgraph_gen::trace_unsafety(
+    let mode = if args.include_tests {
+        CompileMode::Test
+    } else {
+        CompileMode::Build
+    };
+    let tainted = trawl_source::get_tainted(
+        &config,
+        &ws,
+        &args.package,
+        mode,
+        args.target_opts.compile_filter(),
+    )?;
+    let unsafe_sites = &tainted.unsafe_sites;
+    let (root_targets, bitcode_units) = targets::root_targets(
+        &tainted.units,
+        ws.current()?.package_id(),
+        &args.target_opts,
+    );
+    let callgraph = callgraph_gen::gen_callgraph(&bitcode_units)?;
+    let run = report::RunInfo::new(&ws)?;
+
+    let strategy = if args.match_by_name {
+        callgraph_gen::MatchStrategy::Name
+    } else {
+        callgraph_gen::MatchStrategy::DebugInfo
+    };
+    let matches = callgraph_gen::match_unsafe_sites(&callgraph, unsafe_sites, strategy);
+    info!(
+        "Matched {} unsafe sites by debug info and {} by name, {} were not found in the callgraph",
+        matches.by_debuginfo,
+        matches.by_name,
+        matches.unmatched.len()
+    );
+
+    if let Some(function) = &args.explain {
+        let explanations = callgraph_gen::explain_unsafety(&callgraph, function, &matches)
+            .ok_or_else(|| anyhow!("Could not find function `{}` in the callgraph", function))?;
+        return report::print_explanation(&run, &callgraph, &matches, function, &explanations);
+    }
+
+    let weighting = if args.per_function {
+        callgraph_gen::Weighting::PerFunction
+    } else if args.op_weights.is_empty() {
+        callgraph_gen::Weighting::PerSite
+    } else {
+        callgraph_gen::Weighting::PerOperation(args.op_weights.iter().copied().collect())
+    };
+    let call_weighting = if args.weight_by_calls {
+        callgraph_gen::CallWeighting::CallSites {
+            loop_weight: args.loop_weight,
+        }
+    } else {
+        callgraph_gen::CallWeighting::Reachability
+    };
+    let badness = callgraph_gen::trace_unsafety(
         &callgraph,
-        &crate_name,
-        &tainted,
-    ))
+        &root_targets,
+        &matches,
+        &weighting,
+        call_weighting,
+    );
+    let analysis = report::Analysis {
+        targets: &root_targets,
+        callgraph: &callgraph,
+        unsafe_sites,
+        matches: &matches,
+        compiled_files: &tainted.compiled_files,
+        badness: &badness,
+    };
+    report::emit(&args.report_opts, &run, &analysis)?;
+    mark_source::mark_source(&args.mark_opts, &badness)
+}
+
+/// Parses an `--op-weight` argument, like `asm=10`
+fn parse_op_weight(arg: &str) -> anyhow::Result<(unsafe_site::OpCategory, u32)> {
+    let (category, weight) = arg
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected CATEGORY=WEIGHT, found `{}`", arg))?;
+    let category = category.parse().map_err(|err: String| anyhow!(err))?;
+    Ok((category, weight.parse()?))
 }
 
 fn find_package<'ws>(ws: &'ws Workspace, name: &str) -> Option<&'ws Package> {
@@ -94,15 +207,5 @@ fn crate_name(ws: &Workspace, package: &Option<String>) -> anyhow::Result<String
 fn main() -> anyhow::Result<()> {
     env_logger::init();
     let args = Args::from_args();
-    real_main(&args).and_then(|badness| {
-        println!("Badness  Function");
-        let mut badness_out_list: Vec<(&str, &u32)> =
-            badness.iter().map(|(a, (b, _))| (a as &str, b)).collect();
-        badness_out_list.sort_by_key(|(a, b)| (u32::MAX - *b, *a));
-        for (label, badness) in badness_out_list {
-            println!("    {badness:03}  {label}");
-        }
-        mark_source::mark_source(&args.mark_opts, &badness)?;
-        Ok(())
-    })
+    real_main(&args)
 }
@@ -8,9 +8,8 @@ use regex::Regex;
 use structopt::StructOpt;
 use tempfile::NamedTempFile;
 
-use crate::utils::LabelInfo;
-
-type BadnessMap = HashMap<String, (u32, LabelInfo)>;
+use crate::callgraph_gen::BadnessMap;
+use crate::utils::debugloc_path;
 
 #[derive(StructOpt, Debug)]
 pub struct MarkOpts {
@@ -46,15 +45,9 @@ pub fn mark_source(opts: &MarkOpts, badness: &BadnessMap) -> Result<()> {
 fn group_by_path(badness: &BadnessMap) -> HashMap<PathBuf, BadnessMap> {
     let mut grouped = HashMap::new();
     for entry in badness {
-        if let Some(debugloc) = &entry.1 .1.debugloc {
-            let path = debugloc
-                .directory
-                .as_ref()
-                .map_or(PathBuf::from(&debugloc.filename), |directory| {
-                    PathBuf::from(directory).join(&debugloc.filename)
-                });
+        if let Some(debugloc) = &entry.1.label_info.debugloc {
             grouped
-                .entry(path)
+                .entry(debugloc_path(debugloc))
                 .or_insert_with(HashMap::new)
                 .insert(entry.0.clone(), entry.1.clone());
         }
@@ -97,11 +90,11 @@ fn extract_line_numbers(opts: &MarkOpts, badness: &BadnessMap) -> Vec<usize> {
         .iter()
         .filter_map(|entry| {
             if (!opts.no_mark_closures || !entry.0.ends_with("{{closure}}"))
-                && entry.1 .0 >= opts.threshold
+                && entry.1.total >= opts.threshold
             {
                 entry
                     .1
-                     .1
+                    .label_info
                     .debugloc
                     .as_ref()
                     .map(|debugloc| debugloc.line as usize)
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use anyhow::Result;
+
+use super::RunInfo;
+use crate::callgraph_gen::{Explanation, SiteMatches};
+use crate::unsafe_site::{OpCategory, UnsafeOperation};
+use crate::utils::{debugloc_path, CallGraph, CallSites};
+
+/// Formats a label the way it is printed in the badness table, with its source location if
+/// debug info is available
+fn fmt_hop(run: &RunInfo, callgraph: &CallGraph, label: &str) -> String {
+    let label_info = callgraph.label_to_label_info.get(label);
+    let name = label_info
+        .and_then(|label_info| label_info.short_label.as_deref())
+        .unwrap_or(label);
+    match label_info.and_then(|label_info| label_info.debugloc.as_ref()) {
+        Some(debugloc) => format!(
+            "{name}  ({}:{})",
+            run.relative_path(&debugloc_path(debugloc)).display(),
+            debugloc.line
+        ),
+        None => name.to_string(),
+    }
+}
+
+/// Describes where and how often a function is called
+fn fmt_call_sites(run: &RunInfo, sites: &CallSites) -> String {
+    let location = match sites.debuglocs.first() {
+        Some(debugloc) => format!(
+            "called at {}:{}",
+            run.relative_path(&debugloc_path(debugloc)).display(),
+            debugloc.line
+        ),
+        None => "called".to_string(),
+    };
+    match (sites.count, sites.in_loop) {
+        (1, 0) => location,
+        (1, 1) => format!("{location}, in a loop"),
+        (count, 0) => format!("{location} ({count} call sites)"),
+        (count, in_loop) => format!("{location} ({count} call sites, {in_loop} in a loop)"),
+    }
+}
+
+/// Lists the categories of the operations of an unsafe site, with how many there are of each
+fn fmt_operations(operations: &[UnsafeOperation]) -> String {
+    let mut counts: BTreeMap<OpCategory, usize> = BTreeMap::new();
+    for op in operations {
+        *counts.entry(op.category).or_insert(0) += 1;
+    }
+    let categories: Vec<String> = counts
+        .into_iter()
+        .map(|(category, count)| match count {
+            1 => category.to_string(),
+            count => format!("{category} x{count}"),
+        })
+        .collect();
+    format!("does {}", categories.join(", "))
+}
+
+pub fn print_explanation(
+    run: &RunInfo,
+    callgraph: &CallGraph,
+    matches: &SiteMatches,
+    function: &str,
+    explanations: &[Explanation],
+) -> Result<()> {
+    let mut out = io::stdout().lock();
+    if explanations.is_empty() {
+        writeln!(out, "`{function}` does not reach any unsafe code")?;
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "`{function}` reaches {} unsafe source(s):",
+        explanations.len()
+    )?;
+    for explanation in explanations {
+        writeln!(out)?;
+        writeln!(out, "{}", fmt_hop(run, callgraph, &explanation.source))?;
+        for site in &explanation.sites {
+            // Several versions of the crate are linked, so tell which one the site is in
+            let version = if matches.ambiguous_crates.contains(&site.crate_name) {
+                format!(" in {}", matches.crate_key(site))
+            } else {
+                String::new()
+            };
+            writeln!(
+                out,
+                "    {} at {}:{}:{}{version}",
+                site.kind,
+                run.relative_path(&site.file).display(),
+                site.span.start_line,
+                site.span.start_column
+            )?;
+            if !site.operations.is_empty() {
+                writeln!(out, "      {}", fmt_operations(&site.operations))?;
+            }
+        }
+        writeln!(out, "  reached through")?;
+        for (depth, hop) in explanation.chain.iter().enumerate() {
+            let Some(call) = depth.checked_sub(1).and_then(|i| explanation.calls.get(i)) else {
+                writeln!(out, "       {}", fmt_hop(run, callgraph, hop))?;
+                continue;
+            };
+            let kind = if call.kind.is_indirect() {
+                format!("  [{}]", call.kind.description())
+            } else {
+                String::new()
+            };
+            writeln!(out, "    -> {}{kind}", fmt_hop(run, callgraph, hop))?;
+            writeln!(out, "         {}", fmt_call_sites(run, &call.sites))?;
+        }
+    }
+    Ok(())
+}
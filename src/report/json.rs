@@ -0,0 +1,133 @@
+//! JSON and JSON Lines output. The schema is documented in the README and versioned by
+//! `SCHEMA_VERSION`, which must be bumped whenever a field is removed or changes meaning.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::{crate_of_label, Analysis, RunInfo};
+use crate::callgraph_gen::Badness;
+use crate::utils::debugloc_path;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct SiteMatches {
+    debuginfo: usize,
+    name: usize,
+    unmatched: usize,
+}
+
+#[derive(Serialize)]
+struct Header<'a> {
+    schema_version: u32,
+    siderophile_version: &'static str,
+    rustc_version: Option<String>,
+    llvm_version: Option<String>,
+    package: &'a str,
+    features: &'a [String],
+    site_matches: SiteMatches,
+}
+
+impl<'a> Header<'a> {
+    fn new(run: &'a RunInfo, analysis: &Analysis) -> Self {
+        let rustc = rustc_version::version_meta().ok();
+        Self {
+            schema_version: SCHEMA_VERSION,
+            siderophile_version: env!("CARGO_PKG_VERSION"),
+            rustc_version: rustc.as_ref().map(|meta| meta.semver.to_string()),
+            llvm_version: rustc
+                .as_ref()
+                .and_then(|meta| meta.llvm_version.as_ref())
+                .map(|llvm| format!("{}.{}", llvm.major, llvm.minor)),
+            package: &run.package,
+            features: &run.features,
+            site_matches: SiteMatches {
+                debuginfo: analysis.matches.by_debuginfo,
+                name: analysis.matches.by_name,
+                unmatched: analysis.matches.unmatched.len(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Function<'a> {
+    label: &'a str,
+    full_label: Option<&'a str>,
+    badness: u32,
+    badness_by_crate: &'a BTreeMap<String, u32>,
+    indirect_badness: u32,
+    targets: &'a BTreeSet<String>,
+    #[serde(rename = "crate")]
+    crate_name: Option<&'a str>,
+    file: Option<String>,
+    line: Option<u32>,
+}
+
+impl<'a> Function<'a> {
+    fn new(run: &RunInfo, label: &'a str, badness: &'a Badness) -> Self {
+        let label_info = &badness.label_info;
+        Self {
+            label,
+            full_label: label_info.label.as_deref(),
+            badness: badness.total,
+            badness_by_crate: &badness.by_crate,
+            indirect_badness: badness.indirect,
+            targets: &badness.targets,
+            crate_name: crate_of_label(label),
+            file: label_info.debugloc.as_ref().map(|debugloc| {
+                run.relative_path(&debugloc_path(debugloc))
+                    .display()
+                    .to_string()
+            }),
+            line: label_info.debugloc.as_ref().map(|debugloc| debugloc.line),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    #[serde(flatten)]
+    header: Header<'a>,
+    functions: Vec<Function<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Line<'a> {
+    Header(Header<'a>),
+    Function(Function<'a>),
+}
+
+pub fn print_document(
+    run: &RunInfo,
+    analysis: &Analysis,
+    entries: &[(&str, &Badness)],
+) -> Result<()> {
+    let document = Document {
+        header: Header::new(run, analysis),
+        functions: entries
+            .iter()
+            .map(|(label, badness)| Function::new(run, label, badness))
+            .collect(),
+    };
+    let mut out = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, &document)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+pub fn print_lines(run: &RunInfo, analysis: &Analysis, entries: &[(&str, &Badness)]) -> Result<()> {
+    let mut out = io::stdout().lock();
+    serde_json::to_writer(&mut out, &Line::Header(Header::new(run, analysis)))?;
+    writeln!(out)?;
+    for (label, badness) in entries {
+        let line = Line::Function(Function::new(run, label, badness));
+        serde_json::to_writer(&mut out, &line)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+mod explain;
+mod json;
+mod reachability;
+mod sarif;
+mod text;
+mod unmatched;
+
+pub use explain::print_explanation;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use cargo::core::Workspace;
+use structopt::StructOpt;
+
+use crate::callgraph_gen::{Badness, BadnessMap, RootTarget, SiteMatches};
+use crate::unsafe_site::UnsafeSite;
+use crate::utils::CallGraph;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The `Badness  Function` table
+    Text,
+    /// A single JSON document, see `json::SCHEMA_VERSION`
+    Json,
+    /// One JSON object per line, starting with the run header
+    JsonLines,
+    /// A SARIF 2.1.0 log for code-scanning viewers
+    Sarif,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::JsonLines),
+            "sarif" => Ok(Self::Sarif),
+            _ => bail!("Unknown output format `{}`", s),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ReportOpts {
+    #[structopt(
+        long = "format",
+        value_name = "FORMAT",
+        default_value = "text",
+        possible_values = &["text", "json", "jsonl", "sarif"]
+    )]
+    /// Output format
+    format: Format,
+
+    #[structopt(long = "blame")]
+    /// Show which crates the badness of each function comes from, and a summary per crate
+    blame: bool,
+
+    #[structopt(
+        long = "warning-threshold",
+        value_name = "BADNESS",
+        default_value = "10"
+    )]
+    /// Minimum badness for a function to be reported as a SARIF warning instead of a note
+    warning_threshold: u32,
+
+    #[structopt(long = "sarif-unsafe-sites")]
+    /// Also report every unsafe site in the source code as a SARIF result
+    sarif_unsafe_sites: bool,
+
+    #[structopt(long = "unmatched-report", value_name = "FILE", parse(from_os_str))]
+    /// Write the unsafe sites that could not be found in the callgraph to FILE
+    unmatched_report: Option<PathBuf>,
+
+    #[structopt(long = "reachability-report", value_name = "FILE", parse(from_os_str))]
+    /// Write which unsafe sites of each package the root crate can and cannot reach to FILE
+    reachability_report: Option<PathBuf>,
+}
+
+/// Everything a report can be generated from
+pub struct Analysis<'a> {
+    /// The analyzed targets of the root package
+    pub targets: &'a [RootTarget],
+    pub callgraph: &'a CallGraph,
+    pub unsafe_sites: &'a [UnsafeSite],
+    pub matches: &'a SiteMatches<'a>,
+    /// The canonicalized paths of all .rs files used by the build
+    pub compiled_files: &'a HashSet<PathBuf>,
+    pub badness: &'a BadnessMap,
+}
+
+impl Analysis<'_> {
+    /// Whether `crate_name` is one of the analyzed targets
+    fn is_local(&self, crate_name: &str) -> bool {
+        self.targets
+            .iter()
+            .any(|target| target.crate_name == crate_name)
+    }
+}
+
+/// Information about the analyzed package that is reported alongside the results
+pub struct RunInfo {
+    pub package: String,
+    pub features: Vec<String>,
+    pub workspace_root: PathBuf,
+}
+
+impl RunInfo {
+    pub fn new(ws: &Workspace) -> Result<Self> {
+        let package = ws.current()?;
+        let (_packages, resolve) = cargo::ops::resolve_ws(ws)?;
+        let mut features: Vec<String> = resolve
+            .features(package.package_id())
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        features.sort();
+        Ok(Self {
+            package: package.name().to_string(),
+            features,
+            workspace_root: ws.root().to_path_buf(),
+        })
+    }
+
+    /// Makes `path` relative to the workspace root, if it is inside the workspace
+    fn relative_path(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.workspace_root)
+            .map_or_else(|_| path.to_path_buf(), Path::to_path_buf)
+    }
+}
+
+/// Returns the badness entries sorted by descending badness, then by label
+fn sorted_entries(badness: &BadnessMap) -> Vec<(&str, &Badness)> {
+    let mut entries: Vec<(&str, &Badness)> = badness
+        .iter()
+        .map(|(label, badness)| (label as &str, badness))
+        .collect();
+    entries.sort_by_key(|(label, badness)| (u32::MAX - badness.total, *label));
+    entries
+}
+
+/// The crate a demangled label belongs to, e.g. `foo` for `<foo::Bar as core::Baz>::f`
+fn crate_of_label(label: &str) -> Option<&str> {
+    label
+        .trim_start_matches('<')
+        .split("::")
+        .next()
+        .filter(|name| !name.is_empty())
+}
+
+pub fn emit(opts: &ReportOpts, run: &RunInfo, analysis: &Analysis) -> Result<()> {
+    if let Some(path) = &opts.unmatched_report {
+        unmatched::write_unmatched_report(path, run, analysis)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    if let Some(path) = &opts.reachability_report {
+        reachability::write_reachability_report(path, run, analysis)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+
+    let entries = sorted_entries(analysis.badness);
+    match opts.format {
+        Format::Text => text::print_table(opts, analysis, &entries),
+        Format::Json => json::print_document(run, analysis, &entries),
+        Format::JsonLines => json::print_lines(run, analysis, &entries),
+        Format::Sarif => sarif::print_log(opts, run, analysis, &entries),
+    }
+    .with_context(|| "Failed to write report")
+}
@@ -0,0 +1,99 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+
+use super::{Analysis, RunInfo};
+use crate::callgraph_gen::reachable_labels;
+use crate::unsafe_site::UnsafeSite;
+
+#[derive(Default)]
+struct PackageSites<'a> {
+    reachable: Vec<&'a UnsafeSite>,
+    /// Sites that are compiled but never called from the root crate, or not compiled at all
+    unreachable: Vec<&'a UnsafeSite>,
+    /// Declarations and code evaluated at compile time, which are neither reachable nor
+    /// unreachable since they have no code in the binary
+    not_code: Vec<&'a UnsafeSite>,
+}
+
+fn write_sites(out: &mut impl Write, run: &RunInfo, sites: &[&UnsafeSite]) -> Result<()> {
+    for site in sites {
+        writeln!(
+            out,
+            "    {} in {} at {}:{}:{}",
+            site.kind,
+            site.item,
+            run.relative_path(&site.file).display(),
+            site.span.start_line,
+            site.span.start_column
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes, for every package, which of its unsafe sites the root crate can reach and which it
+/// cannot to `path`
+pub fn write_reachability_report(path: &Path, run: &RunInfo, analysis: &Analysis) -> Result<()> {
+    let reachable = reachable_labels(analysis.callgraph, analysis.targets);
+    let reachable_sites: HashSet<&UnsafeSite> = analysis
+        .matches
+        .label_to_sites
+        .iter()
+        .filter(|(label, _)| reachable.contains(label.as_str()))
+        .flat_map(|(_, sites)| sites.iter().copied())
+        .collect();
+
+    let mut packages: BTreeMap<(&str, &str), PackageSites> = BTreeMap::new();
+    for site in analysis.unsafe_sites {
+        let package_sites = packages
+            .entry((site.package_name.as_str(), site.package_version.as_str()))
+            .or_default();
+        if !site.kind.has_code() {
+            package_sites.not_code.push(site);
+        } else if reachable_sites.contains(site) {
+            package_sites.reachable.push(site);
+        } else {
+            package_sites.unreachable.push(site);
+        }
+    }
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "Reachable  Unreachable  Package")?;
+    let (mut total_reachable, mut total_unreachable) = (0, 0);
+    for ((package, version), sites) in &packages {
+        writeln!(
+            out,
+            "    {:05}        {:05}  {package} {version}",
+            sites.reachable.len(),
+            sites.unreachable.len()
+        )?;
+        total_reachable += sites.reachable.len();
+        total_unreachable += sites.unreachable.len();
+    }
+    writeln!(
+        out,
+        "    {total_reachable:05}        {total_unreachable:05}  Total"
+    )?;
+
+    for ((package, version), sites) in &packages {
+        writeln!(out)?;
+        writeln!(out, "{package} {version}")?;
+        if !sites.reachable.is_empty() {
+            writeln!(out, "  reachable from the root crate:")?;
+            write_sites(&mut out, run, &sites.reachable)?;
+        }
+        if !sites.unreachable.is_empty() {
+            writeln!(out, "  present but unreachable:")?;
+            write_sites(&mut out, run, &sites.unreachable)?;
+        }
+        if !sites.not_code.is_empty() {
+            writeln!(out, "  not code, so neither reachable nor unreachable:")?;
+            write_sites(&mut out, run, &sites.not_code)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
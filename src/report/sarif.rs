@@ -0,0 +1,198 @@
+//! SARIF 2.1.0 output, for uploading results to code-scanning dashboards.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use super::{Analysis, ReportOpts, RunInfo};
+use crate::callgraph_gen::Badness;
+use crate::unsafe_site::UnsafeKind;
+use crate::utils::{debugloc_path, LabelInfo};
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// The id of the rule reported for functions with nonzero badness
+const BADNESS_RULE: &str = "siderophile/badness";
+
+fn unsafe_site_rule(kind: UnsafeKind) -> String {
+    format!("siderophile/{}", kind.id())
+}
+
+fn rules() -> Value {
+    let mut rules = vec![json!({
+        "id": BADNESS_RULE,
+        "name": "ReachesUnsafeCode",
+        "shortDescription": {
+            "text": "Function can reach unsafe code"
+        },
+        "fullDescription": {
+            "text": "The function can evaluate unsafe expressions, either directly or through \
+                     the functions it calls. Its badness approximates how many."
+        },
+        "defaultConfiguration": { "level": "warning" },
+        "helpUri": "https://github.com/trailofbits/siderophile#how-it-works"
+    })];
+    rules.extend(UnsafeKind::ALL.iter().map(|kind| {
+        json!({
+            "id": unsafe_site_rule(*kind),
+            "shortDescription": {
+                "text": format!("Source code contains {}", with_article(*kind))
+            },
+            "defaultConfiguration": { "level": "note" },
+            "helpUri": "https://github.com/trailofbits/siderophile#how-it-works"
+        })
+    }));
+    Value::Array(rules)
+}
+
+fn with_article(kind: UnsafeKind) -> String {
+    match kind {
+        UnsafeKind::StaticMut | UnsafeKind::Union => format!("a {kind}"),
+        _ => format!("an {kind}"),
+    }
+}
+
+/// Forward slashes are valid on every platform and required in URIs
+fn uri_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn artifact_location(run: &RunInfo, path: &Path) -> Value {
+    let relative = run.relative_path(path);
+    let uri = uri_path(&relative);
+    if relative.is_absolute() {
+        let separator = if uri.starts_with('/') { "" } else { "/" };
+        json!({ "uri": format!("file://{separator}{uri}") })
+    } else {
+        json!({ "uri": uri, "uriBaseId": "SRCROOT" })
+    }
+}
+
+fn location(run: &RunInfo, label: &str, label_info: &LabelInfo) -> Value {
+    let mut location = json!({
+        "logicalLocations": [{ "fullyQualifiedName": label, "kind": "function" }]
+    });
+    if let Some(debugloc) = &label_info.debugloc {
+        let mut region = json!({ "startLine": debugloc.line });
+        if let Some(col) = debugloc.col {
+            region["startColumn"] = json!(col);
+        }
+        location["physicalLocation"] = json!({
+            "artifactLocation": artifact_location(run, &debugloc_path(debugloc)),
+            "region": region,
+        });
+    }
+    location
+}
+
+fn badness_results(opts: &ReportOpts, run: &RunInfo, entries: &[(&str, &Badness)]) -> Vec<Value> {
+    entries
+        .iter()
+        .map(|(label, badness)| {
+            let level = if badness.total >= opts.warning_threshold {
+                "warning"
+            } else {
+                "note"
+            };
+            json!({
+                "ruleId": BADNESS_RULE,
+                "ruleIndex": 0,
+                "level": level,
+                "message": {
+                    "text": format!("`{label}` has badness {}", badness.total)
+                },
+                "locations": [location(run, label, &badness.label_info)],
+                "properties": {
+                    "badness": badness.total,
+                    "badnessByCrate": badness.by_crate,
+                    "indirectBadness": badness.indirect,
+                    "targets": badness.targets
+                }
+            })
+        })
+        .collect()
+}
+
+fn unsafe_site_results(run: &RunInfo, analysis: &Analysis) -> Vec<Value> {
+    analysis
+        .unsafe_sites
+        .iter()
+        .map(|site| {
+            let rule_index = UnsafeKind::ALL
+                .iter()
+                .position(|kind| *kind == site.kind)
+                .map_or(0, |index| index + 1);
+            json!({
+                "ruleId": unsafe_site_rule(site.kind),
+                "ruleIndex": rule_index,
+                "level": "note",
+                "message": {
+                    "text": format!("`{}` contains {}", site.item, with_article(site.kind))
+                },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": artifact_location(run, &site.file),
+                        "region": {
+                            "startLine": site.span.start_line,
+                            "startColumn": site.span.start_column,
+                            "endLine": site.span.end_line,
+                            "endColumn": site.span.end_column + 1
+                        }
+                    },
+                    "logicalLocations": [{ "fullyQualifiedName": site.item, "kind": "function" }]
+                }],
+                "properties": {
+                    "crate": site.crate_name,
+                    "version": site.package_version,
+                    "operations": site
+                        .operations
+                        .iter()
+                        .map(|op| json!({
+                            "category": op.category.id(),
+                            "startLine": op.span.start_line,
+                            "startColumn": op.span.start_column
+                        }))
+                        .collect::<Vec<_>>()
+                }
+            })
+        })
+        .collect()
+}
+
+pub fn print_log(
+    opts: &ReportOpts,
+    run: &RunInfo,
+    analysis: &Analysis,
+    entries: &[(&str, &Badness)],
+) -> Result<()> {
+    let mut results = badness_results(opts, run, entries);
+    if opts.sarif_unsafe_sites {
+        results.extend(unsafe_site_results(run, analysis));
+    }
+    let log = json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "siderophile",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": rules()
+                }
+            },
+            "originalUriBaseIds": {
+                "SRCROOT": {
+                    "uri": format!("file://{}/", uri_path(&run.workspace_root).trim_end_matches('/'))
+                }
+            },
+            "results": results
+        }]
+    });
+    let mut out = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, &log)?;
+    writeln!(out)?;
+    Ok(())
+}
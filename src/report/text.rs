@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use anyhow::Result;
+
+use super::{Analysis, ReportOpts};
+use crate::callgraph_gen::Badness;
+
+/// Formats how much each crate contributes to a badness, biggest contributor first
+fn fmt_blame(badness: &Badness, analysis: &Analysis) -> String {
+    let mut by_crate: Vec<(&String, &u32)> = badness.by_crate.iter().collect();
+    by_crate.sort_by_key(|(name, weight)| (u32::MAX - **weight, *name));
+    by_crate
+        .into_iter()
+        .map(|(name, weight)| {
+            if analysis.is_local(name) {
+                format!("{weight} local")
+            } else {
+                format!("{weight} from {name}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Prints, for every crate containing unsafe code, the badness it contributes to all functions
+/// of the root package and how many of them it affects
+fn print_crate_summary(
+    out: &mut impl Write,
+    analysis: &Analysis,
+    entries: &[(&str, &Badness)],
+) -> Result<()> {
+    let mut summary: BTreeMap<&str, (u32, usize)> = BTreeMap::new();
+    for (_, badness) in entries {
+        for (name, weight) in &badness.by_crate {
+            let (total, functions) = summary.entry(name).or_default();
+            *total = total.saturating_add(*weight);
+            *functions += 1;
+        }
+    }
+    let mut summary: Vec<(&str, (u32, usize))> = summary.into_iter().collect();
+    summary.sort_by_key(|(name, (total, _))| (u32::MAX - *total, *name));
+
+    writeln!(out)?;
+    writeln!(out, "Badness  Functions  Crate")?;
+    for (name, (total, functions)) in summary {
+        let local = if analysis.is_local(name) {
+            " (local)"
+        } else {
+            ""
+        };
+        writeln!(out, "   {total:04}      {functions:04}  {name}{local}")?;
+    }
+    Ok(())
+}
+
+fn print_functions(
+    out: &mut impl Write,
+    opts: &ReportOpts,
+    analysis: &Analysis,
+    entries: &[(&str, &Badness)],
+) -> Result<()> {
+    writeln!(out, "Badness  Function")?;
+    for (label, badness) in entries {
+        if opts.blame {
+            writeln!(
+                out,
+                "    {:03}  {label}  ({})",
+                badness.total,
+                fmt_blame(badness, analysis)
+            )?;
+        } else {
+            writeln!(out, "    {:03}  {label}", badness.total)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the `Badness  Function` table, one per target if several targets were analyzed
+pub fn print_table(
+    opts: &ReportOpts,
+    analysis: &Analysis,
+    entries: &[(&str, &Badness)],
+) -> Result<()> {
+    let mut out = io::stdout().lock();
+    if let [] | [_] = analysis.targets {
+        print_functions(&mut out, opts, analysis, entries)?;
+    } else {
+        for (i, target) in analysis.targets.iter().enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            writeln!(out, "Target: {}", target.label)?;
+            let target_entries: Vec<(&str, &Badness)> = entries
+                .iter()
+                .filter(|(_, badness)| badness.targets.contains(&target.label))
+                .copied()
+                .collect();
+            print_functions(&mut out, opts, analysis, &target_entries)?;
+        }
+    }
+    if opts.blame {
+        print_crate_summary(&mut out, analysis, entries)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+
+use super::{Analysis, RunInfo};
+use crate::callgraph_gen::{unmatched_cause, UnmatchedCause};
+use crate::unsafe_site::UnsafeSite;
+
+fn fmt_cause(cause: UnmatchedCause) -> &'static str {
+    match cause {
+        UnmatchedCause::NotCompiled => "not compiled (file unused by the build, e.g. due to cfg)",
+        UnmatchedCause::NotInstantiated => "not instantiated (no code generated for the site)",
+        UnmatchedCause::NameMismatch => "name mismatch (no function named like the site's path)",
+        UnmatchedCause::NoCode => "no code (declaration, or evaluated at compile time)",
+    }
+}
+
+/// Writes every unsafe site that was not found in the callgraph to `path`, grouped by crate and
+/// by the probable reason
+pub fn write_unmatched_report(path: &Path, run: &RunInfo, analysis: &Analysis) -> Result<()> {
+    let mut grouped: BTreeMap<(&str, &str), BTreeMap<UnmatchedCause, Vec<&UnsafeSite>>> =
+        BTreeMap::new();
+    for site in analysis.matches.unmatched.iter().copied() {
+        let cause = unmatched_cause(analysis.callgraph, site, analysis.compiled_files);
+        grouped
+            .entry((site.package_name.as_str(), site.package_version.as_str()))
+            .or_default()
+            .entry(cause)
+            .or_default()
+            .push(site);
+    }
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        "{} of {} unsafe sites were not found in the callgraph",
+        analysis.matches.unmatched.len(),
+        analysis.unsafe_sites.len()
+    )?;
+    for ((package, version), causes) in grouped {
+        writeln!(out)?;
+        writeln!(out, "{package} {version}")?;
+        for (cause, sites) in causes {
+            writeln!(out, "  {}: {}", fmt_cause(cause), sites.len())?;
+            for site in sites {
+                writeln!(
+                    out,
+                    "    {} in {} at {}:{}:{}",
+                    site.kind,
+                    site.item,
+                    run.relative_path(&site.file).display(),
+                    site.span.start_line,
+                    site.span.start_column
+                )?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! Which targets of the root package are analyzed, and how they are shown in reports
+
+use cargo::core::{compiler::CompileMode, manifest::TargetKind, PackageId, Target};
+use cargo::ops::CompileFilter;
+use structopt::StructOpt;
+
+use crate::callgraph_gen::{BitcodeUnit, RootTarget};
+use crate::trawl_source::CompiledUnit;
+
+#[derive(StructOpt, Debug)]
+pub struct TargetOpts {
+    #[structopt(long = "lib")]
+    /// Analyze the package's library
+    lib: bool,
+
+    #[structopt(long = "bin", value_name = "NAME", number_of_values = 1)]
+    /// Analyze the binary NAME, may be given several times
+    bin: Vec<String>,
+
+    #[structopt(long = "bins")]
+    /// Analyze all binaries of the package
+    bins: bool,
+
+    #[structopt(long = "examples")]
+    /// Analyze all examples of the package
+    examples: bool,
+
+    #[structopt(long = "tests")]
+    /// Analyze all targets of the package that have tests, built as test harnesses
+    tests: bool,
+
+    #[structopt(long = "benches")]
+    /// Analyze all benchmarks of the package, built as test harnesses
+    benches: bool,
+
+    #[structopt(long = "all-targets")]
+    /// Analyze all targets of the package, including tests and benchmarks
+    all_targets: bool,
+}
+
+impl TargetOpts {
+    /// The targets to build, like `cargo build` with the same flags would. Without any flags,
+    /// that is the library and all binaries. `--tests` and `--benches` targets are built as test
+    /// harnesses even outside of `CompileMode::Test`, as cargo does.
+    pub fn compile_filter(&self) -> CompileFilter {
+        CompileFilter::from_raw_arguments(
+            self.lib,
+            self.bin.clone(),
+            self.bins,
+            Vec::new(),
+            self.tests,
+            Vec::new(),
+            self.examples,
+            Vec::new(),
+            self.benches,
+            self.all_targets,
+        )
+    }
+}
+
+/// How the target of `unit` is shown in reports, e.g. `lib` or `bin foo`. Libraries and binaries
+/// compiled as unit tests are suffixed with `tests`, since they are different crates.
+fn target_label(unit: &CompiledUnit) -> String {
+    let name = unit.target.name();
+    let label = match unit.target.kind() {
+        TargetKind::Lib(_) => "lib".to_string(),
+        TargetKind::Bin => format!("bin {name}"),
+        TargetKind::ExampleLib(_) | TargetKind::ExampleBin => format!("example {name}"),
+        TargetKind::Test => format!("test {name}"),
+        TargetKind::Bench => format!("bench {name}"),
+        TargetKind::CustomBuild => format!("build script {name}"),
+    };
+    let harness_of_own_code = matches!(unit.mode, CompileMode::Test | CompileMode::Bench)
+        && !matches!(unit.target.kind(), TargetKind::Test | TargetKind::Bench);
+    if harness_of_own_code {
+        format!("{label} tests")
+    } else {
+        label
+    }
+}
+
+/// Orders libraries first, then binaries, examples, tests and benchmarks
+const fn kind_rank(kind: &TargetKind) -> u8 {
+    match kind {
+        TargetKind::Lib(_) => 0,
+        TargetKind::Bin => 1,
+        TargetKind::ExampleLib(_) | TargetKind::ExampleBin => 2,
+        TargetKind::Test => 3,
+        TargetKind::Bench => 4,
+        TargetKind::CustomBuild => 5,
+    }
+}
+
+/// Whether `target` of the root package is analyzed. Without any target flags, those are the
+/// library and binaries, and the test targets when tests are built. Examples which cargo builds
+/// alongside the tests are not.
+fn is_selected(filter: &CompileFilter, target: &Target) -> bool {
+    if filter.is_specific() {
+        filter.target_run(target)
+    } else {
+        matches!(
+            target.kind(),
+            TargetKind::Lib(_) | TargetKind::Bin | TargetKind::Test
+        )
+    }
+}
+
+/// Splits the units of the build into the targets of the root package selected by `opts`, and
+/// the bitcode of every unit labelled with the root target it is, if any. Build scripts are left
+/// out, as they do not run as part of the program.
+pub fn root_targets(
+    units: &[CompiledUnit],
+    root: PackageId,
+    opts: &TargetOpts,
+) -> (Vec<RootTarget>, Vec<BitcodeUnit>) {
+    let filter = opts.compile_filter();
+    let mut targets: Vec<(u8, RootTarget)> = Vec::new();
+    let mut bitcode_units = Vec::new();
+    for unit in units.iter().filter(|unit| !unit.target.is_custom_build()) {
+        let target = if unit.package_id == root && is_selected(&filter, &unit.target) {
+            let target = RootTarget {
+                crate_name: unit.crate_name.clone(),
+                label: target_label(unit),
+            };
+            if !targets.iter().any(|(_, known)| known == &target) {
+                targets.push((kind_rank(unit.target.kind()), target.clone()));
+            }
+            Some(target.label)
+        } else {
+            None
+        };
+        bitcode_units.push(BitcodeUnit {
+            path: unit.bitcode_path(),
+            target,
+        });
+    }
+    targets.sort_by(|(rank_a, a), (rank_b, b)| (rank_a, &a.label).cmp(&(rank_b, &b.label)));
+    (
+        targets.into_iter().map(|(_, target)| target).collect(),
+        bitcode_units,
+    )
+}
@@ -1,7 +1,7 @@
 #![forbid(unsafe_code)]
 
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, HashSet, VecDeque},
     error::Error,
     fmt,
     fs::File,
@@ -10,14 +10,24 @@ use std::{
     string::FromUtf8Error,
 };
 
+use proc_macro2::Span;
 use quote::ToTokens;
 use syn::{
-    punctuated::Punctuated, visit, Expr, GenericArgument, ImplItemFn, ItemFn, ItemImpl, ItemMod,
-    ItemTrait, PathArguments, TraitItemFn,
+    punctuated::Punctuated, spanned::Spanned, visit, Arm, Attribute, Expr, ForeignItem,
+    ForeignItemFn, ForeignItemStatic, GenericArgument, GenericParam, ImplItem, ImplItemConst,
+    ImplItemFn, Item, ItemConst, ItemFn, ItemForeignMod, ItemImpl, ItemMod, ItemStatic, ItemTrait,
+    ItemUnion, LitStr, Macro, Member, PathArguments, Signature, StaticMutability, Stmt, TraitItem,
+    TraitItemConst, TraitItemFn, UnOp,
 };
 
-/// A formatted list of Rust items that are unsafe
-pub struct UnsafeItems(pub(crate) Vec<String>);
+use super::cfg::CfgSet;
+use crate::unsafe_site::{
+    DefaultMethod, OpCategory, SourceSpan, UnsafeKind, UnsafeOperation, UnsafeSite,
+};
+
+/// Every unsafe site in a file. An item containing several `unsafe` blocks has one site for
+/// every block.
+pub struct UnsafeItems(pub(crate) Vec<UnsafeSite>);
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -36,19 +46,103 @@ impl fmt::Display for ScanFileError {
     }
 }
 
-struct SiderophileSynVisitor {
+/// Where the scanned file comes from
+pub struct FileOrigin<'a> {
+    /// The name of the crate, as used in paths
+    pub crate_name: &'a str,
+    pub package_name: &'a str,
+    pub package_version: &'a str,
+    /// Maps the names the crate's dependencies are known by in its source to their crate names
+    pub extern_crates: &'a HashMap<String, String>,
+    /// The path of the module the file is inside its crate, if the file is in the crate's
+    /// module tree. Otherwise it is guessed from the file's path.
+    pub module_path: Option<&'a [String]>,
+    /// The configuration the crate is compiled with. Items it leaves out are not scanned.
+    pub cfg: &'a CfgSet,
+}
+
+/// The items declared in a file whose use needs `unsafe`. Paths are not resolved, so operations
+/// inside unsafe sites are told apart by the names of these items only.
+#[derive(Default)]
+struct UnsafeDecls {
+    /// `unsafe fn`s, including methods
+    unsafe_fns: HashSet<String>,
+    /// Functions declared in `extern` blocks
+    foreign_fns: HashSet<String>,
+    /// `static mut`s, and statics declared in `extern` blocks
+    statics: HashSet<String>,
+    /// The fields of unions
+    union_fields: HashSet<String>,
+}
+
+impl<'ast> visit::Visit<'ast> for UnsafeDecls {
+    fn visit_signature(&mut self, i: &Signature) {
+        if i.unsafety.is_some() {
+            self.unsafe_fns.insert(i.ident.to_string());
+        }
+    }
+
+    fn visit_foreign_item_fn(&mut self, i: &ForeignItemFn) {
+        self.foreign_fns.insert(i.sig.ident.to_string());
+    }
+
+    fn visit_foreign_item_static(&mut self, i: &ForeignItemStatic) {
+        self.statics.insert(i.ident.to_string());
+    }
+
+    fn visit_item_static(&mut self, i: &ItemStatic) {
+        if matches!(i.mutability, StaticMutability::Mut(_)) {
+            self.statics.insert(i.ident.to_string());
+        }
+        visit::visit_item_static(self, i);
+    }
+
+    fn visit_item_union(&mut self, i: &ItemUnion) {
+        self.union_fields.extend(
+            i.fields
+                .named
+                .iter()
+                .filter_map(|field| field.ident.as_ref().map(ToString::to_string)),
+        );
+    }
+}
+
+struct SiderophileSynVisitor<'a> {
     /// Where we log all the findings
-    buf: Vec<String>,
+    buf: Vec<UnsafeSite>,
 
     /// Keeps track of what the current module path is (this includes trait defs and impls)
     cur_mod_path: VecDeque<String>,
 
-    /// Count unsafe usage inside tests
-    include_tests: bool,
+    origin: &'a FileOrigin<'a>,
+
+    /// The file being scanned
+    file: &'a Path,
+
+    /// Whether we are inside a trait impl, so that unsafe methods are trait methods
+    in_trait_impl: bool,
+
+    /// The trait whose definition we are inside, if any
+    cur_trait: Option<String>,
+
+    /// Inside the default body of a trait method, the trait and the position in `cur_mod_path`
+    /// where the path of the method starts
+    default_method: Option<(String, usize)>,
+
+    /// Whether we are in the initializer of a `static` or a `const`, which is evaluated at
+    /// compile time
+    in_initializer: bool,
+
+    /// The items of the file whose use needs `unsafe`
+    decls: UnsafeDecls,
+
+    /// The index in `buf` of the unsafe block or unsafe function body we are inside, which the
+    /// operations found are recorded in
+    cur_site: Option<usize>,
 }
 
-impl SiderophileSynVisitor {
-    fn new(prefix: String, include_tests: bool) -> Self {
+impl<'a> SiderophileSynVisitor<'a> {
+    fn new(prefix: String, origin: &'a FileOrigin, file: &'a Path, decls: UnsafeDecls) -> Self {
         let mut cur_mod_path = VecDeque::new();
         cur_mod_path.push_back(prefix);
         let buf = Vec::new();
@@ -56,77 +150,665 @@ impl SiderophileSynVisitor {
         Self {
             buf,
             cur_mod_path,
-            include_tests,
+            origin,
+            file,
+            in_trait_impl: false,
+            cur_trait: None,
+            default_method: None,
+            in_initializer: false,
+            decls,
+            cur_site: None,
         }
     }
-}
 
-/// Will return true for #[cfg(test)] decorated modules.
-///
-/// This function is a somewhat of a hack and will probably misinterpret more
-/// advanced cfg expressions. A better way to do this would be to let rustc emit
-/// every single source file path and span within each source file and use that
-/// as a general filter for included code.
-/// TODO: Investigate if the needed information can be emitted by rustc today.
-fn is_test_mod(i: &ItemMod) -> bool {
-    i.attrs
-        .iter()
-        .filter(|attr| attr.path().is_ident("cfg"))
-        .any(|attr| {
-            attr.parse_nested_meta(|meta| {
-                if meta.path.is_ident("test") {
-                    Ok(())
+    /// Records an unsafe site of the given kind in the current item
+    fn push_site(&mut self, kind: UnsafeKind, span: Span) {
+        self.push_site_between(kind, span, span);
+    }
+
+    /// Records an unsafe site of the given kind in the current item, spanning from the start of
+    /// `first` to the end of `last`, like the header of an item
+    fn push_site_between(&mut self, kind: UnsafeKind, first: Span, last: Span) {
+        self.buf.push(UnsafeSite {
+            item: fmt_mod_path(&self.cur_mod_path),
+            kind,
+            file: self.file.to_path_buf(),
+            span: SourceSpan {
+                end_line: source_span(last).end_line,
+                end_column: source_span(last).end_column,
+                ..source_span(first)
+            },
+            crate_name: self.origin.crate_name.to_string(),
+            package_name: self.origin.package_name.to_string(),
+            package_version: self.origin.package_version.to_string(),
+            default_method: self
+                .default_method
+                .as_ref()
+                .map(|(trait_name, start)| DefaultMethod {
+                    trait_name: trait_name.clone(),
+                    item: self
+                        .cur_mod_path
+                        .iter()
+                        .skip(*start)
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join("::"),
+                }),
+            operations: Vec::new(),
+        });
+    }
+
+    /// Makes the site pushed last the one operations are recorded in, returning the site they
+    /// were recorded in before
+    fn enter_last_site(&mut self) -> Option<usize> {
+        self.cur_site.replace(self.buf.len() - 1)
+    }
+
+    /// Records an operation of the given category in the unsafe site we are inside, if any
+    fn push_op(&mut self, category: OpCategory, span: Span) {
+        if let Some(site) = self.cur_site.and_then(|site| self.buf.get_mut(site)) {
+            site.operations.push(UnsafeOperation {
+                category,
+                span: source_span(span),
+            });
+        }
+    }
+
+    /// The category of the operation `expr` is, if it needs `unsafe`. Only the expression itself
+    /// is looked at, not its operands.
+    fn op_category(&self, expr: &Expr) -> Option<OpCategory> {
+        match expr {
+            // Without types, references cannot be told apart from raw pointers, so only what is
+            // a raw pointer by its syntax is known to be one
+            Expr::Unary(expr) if matches!(expr.op, UnOp::Deref(_)) => {
+                Some(if is_raw_pointer(&expr.expr) {
+                    OpCategory::RawPointerDeref
                 } else {
-                    // We drop this error anyways.
-                    Err(meta.error("Expected `cfg(test)`"))
+                    OpCategory::UnknownDeref
+                })
+            }
+            Expr::Call(call) => match &*call.func {
+                Expr::Path(func) if func.qself.is_none() => self.call_category(&func.path),
+                _ => None,
+            },
+            Expr::MethodCall(call) => {
+                let name = call.method.to_string();
+                if is_unchecked(&name) {
+                    Some(OpCategory::UncheckedCall)
+                } else if self.decls.unsafe_fns.contains(&name)
+                    || STD_UNSAFE_METHODS.contains(&name.as_str())
+                    || (RAW_POINTER_METHODS.contains(&name.as_str())
+                        && is_raw_pointer(&call.receiver))
+                {
+                    Some(OpCategory::UnsafeCall)
+                } else {
+                    None
+                }
+            }
+            Expr::Path(expr) => expr
+                .path
+                .segments
+                .last()
+                .filter(|segment| self.decls.statics.contains(&segment.ident.to_string()))
+                .map(|_| OpCategory::StaticMutAccess),
+            Expr::Field(expr) => match &expr.member {
+                Member::Named(field) if self.decls.union_fields.contains(&field.to_string()) => {
+                    Some(OpCategory::UnionFieldRead)
                 }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The category of a call to the function at `path`, if it needs `unsafe`
+    fn call_category(&self, path: &syn::Path) -> Option<OpCategory> {
+        let mut segments = path
+            .segments
+            .iter()
+            .rev()
+            .map(|segment| segment.ident.to_string());
+        let name = segments.next()?;
+        let module = segments.next();
+        let first = path.segments.first().map(|first| first.ident.to_string());
+        let from_sys_crate =
+            path.segments.len() > 1 && first.is_some_and(|first| first.ends_with("_sys"));
+        if name == "transmute" || name == "transmute_copy" {
+            Some(OpCategory::Transmute)
+        } else if from_sys_crate || self.decls.foreign_fns.contains(&name) {
+            Some(OpCategory::FfiCall)
+        } else if is_unchecked(&name) {
+            Some(OpCategory::UncheckedCall)
+        } else if self.decls.unsafe_fns.contains(&name)
+            || STD_UNSAFE_FNS.iter().any(|(std_module, fns)| {
+                module.as_deref() == Some(*std_module) && fns.contains(&name.as_str())
             })
-            .is_ok()
+        {
+            Some(OpCategory::UnsafeCall)
+        } else {
+            None
+        }
+    }
+
+    /// Visits the items of an impl, once the current path is the impl's
+    fn walk_impl(&mut self, i: &ItemImpl) {
+        if let Some(unsafety) = i.unsafety {
+            self.push_site_between(UnsafeKind::UnsafeImpl, unsafety.span, i.self_ty.span());
+        }
+        visit::visit_item_impl(self, i);
+    }
+
+    /// Visits the attributes and the initializer of a `static` or a `const` item named `ident`
+    fn walk_initializer(&mut self, ident: &syn::Ident, attrs: &[Attribute], expr: &Expr) {
+        self.cur_mod_path.push_back(ident.to_string());
+        for attr in attrs {
+            visit::Visit::visit_attribute(self, attr);
+        }
+        let was_in_initializer = std::mem::replace(&mut self.in_initializer, true);
+        visit::Visit::visit_expr(self, expr);
+        self.in_initializer = was_in_initializer;
+        self.cur_mod_path.pop_back();
+    }
+
+    /// Formats `path` as seen from outside the crate if it starts with the name of a dependency,
+    /// using the dependency's crate name
+    fn extern_path(&self, path: &syn::Path) -> Option<String> {
+        let first = path.segments.first()?;
+        let crate_name = self.origin.extern_crates.get(&first.ident.to_string())?;
+        let mut path = path.clone();
+        path.leading_colon = None;
+        if let Some(first) = path.segments.first_mut() {
+            first.ident = syn::Ident::new(crate_name, first.ident.span());
+        }
+        Some(fmt_syn_path(path))
+    }
+
+    /// Formats a path in a self type the way symbol names spell it: generic parameters and
+    /// primitive types as they are, and other types with the path of the crate they are in
+    fn fmt_type_path(&self, path: &syn::Path, params: &HashSet<String>) -> String {
+        if let Some(ident) = path.get_ident() {
+            let name = ident.to_string();
+            if params.contains(&name) || PRIMITIVE_TYPES.contains(&name.as_str()) {
+                return name;
+            }
+        }
+        if let Some(path) = self.extern_path(path) {
+            return path;
+        }
+        let fmt_path = fmt_syn_path(path.clone());
+        match path.segments.first().map(|first| first.ident.to_string()) {
+            Some(first) if first == "std" => std_defining_path(&fmt_path),
+            Some(first) if first == "core" || first == "alloc" => fmt_path,
+            Some(first) if first == "crate" => {
+                format!("{}{}", self.origin.crate_name, &fmt_path["crate".len()..])
+            }
+            _ => format!("{}::{fmt_path}", fmt_mod_path(&self.cur_mod_path)),
+        }
+    }
+
+    /// Formats the self type of an impl that is not a path, like `&mut [T]` or `dyn Trait`, the
+    /// way rustc's demangler prints it, so that `impl<T> Trait for &T` is named `<&T as Trait>`.
+    /// Lifetimes are left out, like in symbol names. Returns `None` for types that cannot be
+    /// named without expanding them, like macros.
+    fn fmt_type(&self, ty: &syn::Type, params: &HashSet<String>) -> Option<String> {
+        Some(match ty {
+            syn::Type::Path(ty) if ty.qself.is_none() => self.fmt_type_path(&ty.path, params),
+            syn::Type::Reference(ty) => {
+                let mutability = if ty.mutability.is_some() { "mut " } else { "" };
+                format!("&{mutability}{}", self.fmt_type(&ty.elem, params)?)
+            }
+            syn::Type::Ptr(ty) => {
+                let mutability = if ty.mutability.is_some() {
+                    "mut"
+                } else {
+                    "const"
+                };
+                format!("*{mutability} {}", self.fmt_type(&ty.elem, params)?)
+            }
+            syn::Type::Slice(ty) => format!("[{}]", self.fmt_type(&ty.elem, params)?),
+            syn::Type::Array(ty) => {
+                // Lengths that are neither literals nor const parameters are not evaluated
+                let len = match &ty.len {
+                    Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Int(len),
+                        ..
+                    }) => len.base10_digits().to_string(),
+                    Expr::Path(len) => len.path.get_ident()?.to_string(),
+                    _ => "_".to_string(),
+                };
+                format!("[{}; {len}]", self.fmt_type(&ty.elem, params)?)
+            }
+            syn::Type::Tuple(ty) => {
+                let elems = ty
+                    .elems
+                    .iter()
+                    .map(|elem| self.fmt_type(elem, params))
+                    .collect::<Option<Vec<_>>>()?;
+                if elems.len() == 1 {
+                    format!("({},)", elems[0])
+                } else {
+                    format!("({})", elems.join(","))
+                }
+            }
+            syn::Type::TraitObject(ty) => {
+                let bounds = ty
+                    .bounds
+                    .iter()
+                    .filter_map(|bound| match bound {
+                        syn::TypeParamBound::Trait(bound) => Some(bound),
+                        _ => None,
+                    })
+                    .map(|bound| match bound.path.get_ident() {
+                        Some(ident) if ident == "Send" || ident == "Sync" => {
+                            format!("core::marker::{ident}")
+                        }
+                        _ => self.fmt_type_path(&bound.path, params),
+                    })
+                    .collect::<Vec<_>>();
+                format!("dyn {}", bounds.join("+"))
+            }
+            syn::Type::Paren(ty) => self.fmt_type(&ty.elem, params)?,
+            syn::Type::Group(ty) => self.fmt_type(&ty.elem, params)?,
+            syn::Type::Never(_) => "!".to_string(),
+            _ => return None,
+        })
+    }
+}
+
+/// The types rustc does not name with a path
+const PRIMITIVE_TYPES: [&str; 17] = [
+    "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64",
+    "i128", "isize", "f32", "f64",
+];
+
+/// Modules and items of `std` that are re-exported from `core`, `alloc` or a private module of
+/// `std`, with the path they are defined at
+const STD_REEXPORTS: [(&str, &str); 46] = [
+    ("any", "core::any"),
+    ("array", "core::array"),
+    ("cell", "core::cell"),
+    ("char", "core::char"),
+    ("clone", "core::clone"),
+    ("cmp", "core::cmp"),
+    ("convert", "core::convert"),
+    ("default", "core::default"),
+    ("fmt", "core::fmt"),
+    ("future", "core::future"),
+    ("hash", "core::hash"),
+    ("hint", "core::hint"),
+    ("iter", "core::iter"),
+    ("marker", "core::marker"),
+    ("mem", "core::mem"),
+    ("num", "core::num"),
+    ("ops", "core::ops"),
+    ("option", "core::option"),
+    ("pin", "core::pin"),
+    ("ptr", "core::ptr"),
+    ("result", "core::result"),
+    ("slice", "core::slice"),
+    ("str", "core::str"),
+    ("task", "core::task"),
+    ("sync::atomic", "core::sync::atomic"),
+    ("time::Duration", "core::time::Duration"),
+    ("boxed", "alloc::boxed"),
+    ("rc", "alloc::rc"),
+    ("string", "alloc::string"),
+    ("vec", "alloc::vec"),
+    ("sync::Arc", "alloc::sync::Arc"),
+    ("sync::Weak", "alloc::sync::Weak"),
+    (
+        "collections::BTreeMap",
+        "alloc::collections::btree::map::BTreeMap",
+    ),
+    (
+        "collections::BTreeSet",
+        "alloc::collections::btree::set::BTreeSet",
+    ),
+    (
+        "collections::BinaryHeap",
+        "alloc::collections::binary_heap::BinaryHeap",
+    ),
+    (
+        "collections::LinkedList",
+        "alloc::collections::linked_list::LinkedList",
+    ),
+    (
+        "collections::VecDeque",
+        "alloc::collections::vec_deque::VecDeque",
+    ),
+    ("collections::btree_map", "alloc::collections::btree::map"),
+    ("collections::btree_set", "alloc::collections::btree::set"),
+    (
+        "collections::binary_heap",
+        "alloc::collections::binary_heap",
+    ),
+    (
+        "collections::linked_list",
+        "alloc::collections::linked_list",
+    ),
+    ("collections::vec_deque", "alloc::collections::vec_deque"),
+    (
+        "collections::HashMap",
+        "std::collections::hash::map::HashMap",
+    ),
+    (
+        "collections::HashSet",
+        "std::collections::hash::set::HashSet",
+    ),
+    ("collections::hash_map", "std::collections::hash::map"),
+    ("collections::hash_set", "std::collections::hash::set"),
+];
+
+/// Spells a path into `std` the way symbol names do, which name items `std` re-exports after
+/// where they are defined, like `core::any::Any` for `std::any::Any` or
+/// `alloc::collections::btree::map::BTreeMap` for `std::collections::BTreeMap`
+fn std_defining_path(path: &str) -> String {
+    let path = path.trim_start_matches("::");
+    path.strip_prefix("std::")
+        .and_then(|std_path| {
+            STD_REEXPORTS.iter().find_map(|(reexport, defining)| {
+                let rest = std_path.strip_prefix(reexport)?;
+                (rest.is_empty() || rest.starts_with("::") || rest.starts_with('<'))
+                    .then(|| format!("{defining}{rest}"))
+            })
+        })
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// The names of the type and const parameters of an impl
+fn impl_params(i: &ItemImpl) -> HashSet<String> {
+    i.generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(param) => Some(param.ident.to_string()),
+            GenericParam::Const(param) => Some(param.ident.to_string()),
+            GenericParam::Lifetime(_) => None,
         })
+        .collect()
+}
+
+/// Columns reported by `proc_macro2` are 0-based and the end is exclusive
+fn source_span(span: Span) -> SourceSpan {
+    let (start, end) = (span.start(), span.end());
+    SourceSpan {
+        start_line: start.line,
+        start_column: start.column + 1,
+        end_line: end.line,
+        end_column: end.column,
+    }
+}
+
+/// Functions that skip a check their caller must uphold instead, besides those named like
+/// `get_unchecked` or `unchecked_add`
+const UNCHECKED_FNS: [&str; 10] = [
+    "set_len",
+    "from_raw_parts",
+    "from_raw_parts_mut",
+    "from_raw",
+    "from_raw_in",
+    "assume_init",
+    "assume_init_read",
+    "assume_init_ref",
+    "assume_init_mut",
+    "assume_init_drop",
+];
+
+fn is_unchecked(name: &str) -> bool {
+    name.contains("_unchecked") || name.starts_with("unchecked_") || UNCHECKED_FNS.contains(&name)
+}
+
+/// Unsafe functions of the standard library, by the module they are called through, as in
+/// `ptr::read`
+const STD_UNSAFE_FNS: [(&str, &[&str]); 3] = [
+    (
+        "ptr",
+        &[
+            "read",
+            "read_unaligned",
+            "read_volatile",
+            "write",
+            "write_unaligned",
+            "write_volatile",
+            "write_bytes",
+            "copy",
+            "copy_nonoverlapping",
+            "replace",
+            "swap",
+            "swap_nonoverlapping",
+            "drop_in_place",
+        ],
+    ),
+    ("mem", &["zeroed", "uninitialized"]),
+    ("alloc", &["alloc", "alloc_zeroed", "dealloc", "realloc"]),
+];
+
+/// Unsafe methods of raw pointers whose names are not used by common safe methods
+const STD_UNSAFE_METHODS: [&str; 13] = [
+    "byte_offset",
+    "offset_from",
+    "read_unaligned",
+    "read_volatile",
+    "write_unaligned",
+    "write_volatile",
+    "write_bytes",
+    "copy_to",
+    "copy_to_nonoverlapping",
+    "copy_from",
+    "copy_from_nonoverlapping",
+    "drop_in_place",
+    "as_uninit_ref",
+];
+
+/// Unsafe methods of raw pointers whose names are also used by common safe methods, so they only
+/// count on a receiver that is a raw pointer by its syntax
+const RAW_POINTER_METHODS: [&str; 5] = ["offset", "add", "sub", "read", "write"];
+
+/// Macros that expand to inline assembly
+const ASM_MACROS: [&str; 2] = ["asm", "llvm_asm"];
+
+/// Attributes that are unsafe without being marked `#[unsafe(...)]` before Rust 2024
+const UNSAFE_ATTRIBUTES: [&str; 3] = ["no_mangle", "export_name", "link_section"];
+
+/// Whether `expr` is a raw pointer by its syntax alone: a cast to `*const T` or `*mut T`, or a
+/// call to `as_ptr` or `as_mut_ptr`
+fn is_raw_pointer(expr: &Expr) -> bool {
+    match expr {
+        Expr::Cast(cast) => matches!(*cast.ty, syn::Type::Ptr(_)),
+        Expr::MethodCall(call) => call.method == "as_ptr" || call.method == "as_mut_ptr",
+        Expr::Paren(expr) => is_raw_pointer(&expr.expr),
+        Expr::Group(expr) => is_raw_pointer(&expr.expr),
+        _ => false,
+    }
 }
 
 fn is_test_fn(i: &ItemFn) -> bool {
     i.attrs.iter().any(|attr| attr.path().is_ident("test"))
 }
 
-impl<'ast> visit::Visit<'ast> for SiderophileSynVisitor {
+fn item_attrs(i: &Item) -> &[Attribute] {
+    match i {
+        Item::Const(i) => i.attrs.as_slice(),
+        Item::Enum(i) => i.attrs.as_slice(),
+        Item::ExternCrate(i) => i.attrs.as_slice(),
+        Item::Fn(i) => i.attrs.as_slice(),
+        Item::ForeignMod(i) => i.attrs.as_slice(),
+        Item::Impl(i) => i.attrs.as_slice(),
+        Item::Macro(i) => i.attrs.as_slice(),
+        Item::Mod(i) => i.attrs.as_slice(),
+        Item::Static(i) => i.attrs.as_slice(),
+        Item::Struct(i) => i.attrs.as_slice(),
+        Item::Trait(i) => i.attrs.as_slice(),
+        Item::TraitAlias(i) => i.attrs.as_slice(),
+        Item::Type(i) => i.attrs.as_slice(),
+        Item::Union(i) => i.attrs.as_slice(),
+        Item::Use(i) => i.attrs.as_slice(),
+        _ => &[],
+    }
+}
+
+fn impl_item_attrs(i: &ImplItem) -> &[Attribute] {
+    match i {
+        ImplItem::Const(i) => i.attrs.as_slice(),
+        ImplItem::Fn(i) => i.attrs.as_slice(),
+        ImplItem::Type(i) => i.attrs.as_slice(),
+        ImplItem::Macro(i) => i.attrs.as_slice(),
+        _ => &[],
+    }
+}
+
+fn trait_item_attrs(i: &TraitItem) -> &[Attribute] {
+    match i {
+        TraitItem::Const(i) => i.attrs.as_slice(),
+        TraitItem::Fn(i) => i.attrs.as_slice(),
+        TraitItem::Type(i) => i.attrs.as_slice(),
+        TraitItem::Macro(i) => i.attrs.as_slice(),
+        _ => &[],
+    }
+}
+
+fn foreign_item_attrs(i: &ForeignItem) -> &[Attribute] {
+    match i {
+        ForeignItem::Fn(i) => i.attrs.as_slice(),
+        ForeignItem::Static(i) => i.attrs.as_slice(),
+        ForeignItem::Type(i) => i.attrs.as_slice(),
+        ForeignItem::Macro(i) => i.attrs.as_slice(),
+        _ => &[],
+    }
+}
+
+/// The attributes of the expressions whose `cfg` is checked. Only blocks are, since `cfg` on
+/// other expressions is unstable.
+fn expr_attrs(i: &Expr) -> &[Attribute] {
+    match i {
+        Expr::Unsafe(i) => i.attrs.as_slice(),
+        Expr::Block(i) => i.attrs.as_slice(),
+        _ => &[],
+    }
+}
+
+impl<'ast> visit::Visit<'ast> for SiderophileSynVisitor<'_> {
     fn visit_file(&mut self, i: &'ast syn::File) {
-        syn::visit::visit_file(self, i);
+        // A `#![cfg]` at the top of the file leaves out the whole module
+        if self.origin.cfg.is_enabled(&i.attrs) {
+            syn::visit::visit_file(self, i);
+        }
+    }
+
+    /// Every item is left out if its `cfg` does not hold
+    fn visit_item(&mut self, i: &Item) {
+        if self.origin.cfg.is_enabled(item_attrs(i)) {
+            // Items nested in a default method body are not copied to each implementor, and
+            // functions nested in an initializer are not evaluated at compile time
+            let cur_trait = self.cur_trait.take();
+            let default_method = self.default_method.take();
+            let was_in_initializer = std::mem::replace(&mut self.in_initializer, false);
+            let cur_site = self.cur_site.take();
+            visit::visit_item(self, i);
+            self.cur_trait = cur_trait;
+            self.default_method = default_method;
+            self.in_initializer = was_in_initializer;
+            self.cur_site = cur_site;
+        }
+    }
+
+    fn visit_impl_item(&mut self, i: &ImplItem) {
+        if self.origin.cfg.is_enabled(impl_item_attrs(i)) {
+            visit::visit_impl_item(self, i);
+        }
+    }
+
+    fn visit_trait_item(&mut self, i: &TraitItem) {
+        if self.origin.cfg.is_enabled(trait_item_attrs(i)) {
+            visit::visit_trait_item(self, i);
+        }
+    }
+
+    fn visit_foreign_item(&mut self, i: &ForeignItem) {
+        if self.origin.cfg.is_enabled(foreign_item_attrs(i)) {
+            visit::visit_foreign_item(self, i);
+        }
+    }
+
+    fn visit_stmt(&mut self, i: &Stmt) {
+        let attrs = match i {
+            Stmt::Local(local) => local.attrs.as_slice(),
+            _ => &[],
+        };
+        if self.origin.cfg.is_enabled(attrs) {
+            visit::visit_stmt(self, i);
+        }
+    }
+
+    fn visit_arm(&mut self, i: &Arm) {
+        if self.origin.cfg.is_enabled(&i.attrs) {
+            visit::visit_arm(self, i);
+        }
     }
 
     /// Free-standing functions
     fn visit_item_fn(&mut self, i: &ItemFn) {
-        // Exclude #[test] functions if not explicitly allowed
-        if !self.include_tests && is_test_fn(i) {
+        // #[test] functions are only compiled into test harnesses
+        if is_test_fn(i) && !self.origin.cfg.is_set("test") {
             return;
         }
 
         self.cur_mod_path.push_back(i.sig.ident.to_string());
 
         // See if this function is marked unsafe
+        let outer_site = self.cur_site;
         if i.sig.unsafety.is_some() {
-            let pp = fmt_mod_path(&self.cur_mod_path);
-            self.buf.push(pp);
+            self.push_site(UnsafeKind::Function, i.span());
+            self.enter_last_site();
         }
 
         trace!("entering function {:?}", i.sig.ident);
         visit::visit_item_fn(self, i);
 
+        self.cur_site = outer_site;
         self.cur_mod_path.pop_back();
     }
 
     fn visit_expr(&mut self, i: &Expr) {
+        if !self.origin.cfg.is_enabled(expr_attrs(i)) {
+            return;
+        }
+        if let Some(category) = self.op_category(i) {
+            self.push_op(category, i.span());
+        }
         match i {
             Expr::Unsafe(i) => {
-                let pp = fmt_mod_path(&self.cur_mod_path);
-                self.buf.push(pp);
+                let kind = if self.in_initializer {
+                    UnsafeKind::InitializerBlock
+                } else {
+                    UnsafeKind::Block
+                };
+                self.push_site(kind, i.span());
+                let outer_site = self.enter_last_site();
                 visit::visit_expr_unsafe(self, i);
+                self.cur_site = outer_site;
             }
+            Expr::Assign(assign) => match &*assign.left {
+                // Writing a union field is safe, unlike reading it
+                Expr::Field(field) => {
+                    visit::Visit::visit_expr(self, &field.base);
+                    visit::Visit::visit_expr(self, &assign.right);
+                }
+                _ => visit::visit_expr_assign(self, assign),
+            },
             Expr::Closure(expr_closure) => {
+                // Closures run when they are called, even if they are created in an initializer
                 self.cur_mod_path.push_back("{{closure}}".to_string());
+                let was_in_initializer = std::mem::replace(&mut self.in_initializer, false);
                 visit::visit_expr_closure(self, expr_closure);
+                self.in_initializer = was_in_initializer;
                 self.cur_mod_path.pop_back();
             }
+            Expr::Const(expr_const) => {
+                let was_in_initializer = std::mem::replace(&mut self.in_initializer, true);
+                visit::visit_expr_const(self, expr_const);
+                self.in_initializer = was_in_initializer;
+            }
             Expr::Path(_) | Expr::Lit(_) => {
                 // Do not count. The expression `f(x)` should count as one
                 // expression, not three.
@@ -138,29 +820,26 @@ impl<'ast> visit::Visit<'ast> for SiderophileSynVisitor {
     }
 
     fn visit_item_mod(&mut self, i: &ItemMod) {
-        if !self.include_tests && is_test_mod(i) {
-            return;
-        }
-
         self.cur_mod_path.push_back(i.ident.to_string());
         visit::visit_item_mod(self, i);
         self.cur_mod_path.pop_back();
     }
 
     fn visit_item_impl(&mut self, i: &ItemImpl) {
+        let was_in_trait_impl = std::mem::replace(&mut self.in_trait_impl, i.trait_.is_some());
+
         // unsafe trait impl's
         if let syn::Type::Path(ref for_path) = &*i.self_ty {
-            let for_path = fmt_syn_path(for_path.path.clone());
+            // Types of dependencies and of `std` are not under the current module
+            let fmt_self_ty = self.fmt_type_path(&for_path.path, &impl_params(i));
             if let Some((_, ref trait_path, _)) = i.trait_ {
                 let trait_path = fmt_syn_path(trait_path.clone());
                 // Save the old path. We heavily modify the path for trait impls
                 let old_cur_mod_path = self.cur_mod_path.clone();
 
                 // We want a trait impl to look like
-                // `<parking_lot_core::util::Option<T> as UncheckedOptionExt<T>>::unchecked_unwrap`
-                self.cur_mod_path.push_back(for_path);
-                let fmt_cur_mod_path = fmt_mod_path(&self.cur_mod_path);
-                let full_impl_path = format!("<{fmt_cur_mod_path} as {trait_path}>");
+                // `<parking_lot_core::util::Option<T> as UncheckedOptionExt<T>>::unchecked_unwrap`.
+                let full_impl_path = format!("<{fmt_self_ty} as {trait_path}>");
 
                 trace!("entering trait impl {}", trait_path);
                 // The new path is just one component long, the whole thing in angled brackets
@@ -168,53 +847,169 @@ impl<'ast> visit::Visit<'ast> for SiderophileSynVisitor {
                 self.cur_mod_path.push_back(full_impl_path);
 
                 // Recurse
-                visit::visit_item_impl(self, i);
+                self.walk_impl(i);
 
                 // Restore the old path
                 self.cur_mod_path = old_cur_mod_path;
                 trace!("exiting trait impl {}", trait_path);
             } else {
                 // Regular impls look like `parking_lot::raw_mutex::RawMutex::unlock_slow`
-                trace!("entering impl {}", for_path);
-                self.cur_mod_path.push_back(for_path.clone());
+                trace!("entering impl {}", fmt_self_ty);
+                let old_cur_mod_path = std::mem::replace(
+                    &mut self.cur_mod_path,
+                    VecDeque::from([fmt_self_ty.clone()]),
+                );
 
-                visit::visit_item_impl(self, i);
+                self.walk_impl(i);
 
-                self.cur_mod_path.pop_back();
-                trace!("exiting impl {}", for_path);
+                self.cur_mod_path = old_cur_mod_path;
+                trace!("exiting impl {}", fmt_self_ty);
             }
+        } else if let Some(fmt_self_ty) = self.fmt_type(&i.self_ty, &impl_params(i)) {
+            // Impls for references, pointers, slices, arrays, tuples and trait objects look like
+            // `<&mut [T] as Trait>::f`, or `<*const T>::f` for inherent impls
+            let full_impl_path = match &i.trait_ {
+                Some((_, trait_path, _)) => {
+                    format!("<{fmt_self_ty} as {}>", fmt_syn_path(trait_path.clone()))
+                }
+                None => format!("<{fmt_self_ty}>"),
+            };
+            trace!("entering impl {}", full_impl_path);
+            let old_cur_mod_path =
+                std::mem::replace(&mut self.cur_mod_path, VecDeque::from([full_impl_path]));
+            self.walk_impl(i);
+            self.cur_mod_path = old_cur_mod_path;
         } else {
-            // I don't know what this case represents
-            visit::visit_item_impl(self, i);
+            // Self types like macros can't be named without expanding them
+            self.walk_impl(i);
         }
+
+        self.in_trait_impl = was_in_trait_impl;
     }
 
     fn visit_item_trait(&mut self, i: &ItemTrait) {
-        // Unsafe traits
         self.cur_mod_path.push_back(i.ident.to_string());
+        self.cur_trait = Some(i.ident.to_string());
+        if let Some(unsafety) = i.unsafety {
+            self.push_site_between(UnsafeKind::UnsafeTrait, unsafety.span, i.ident.span());
+        }
         visit::visit_item_trait(self, i);
         self.cur_mod_path.pop_back();
     }
 
     fn visit_trait_item_fn(&mut self, i: &TraitItemFn) {
-        // Unsafe default-implemented trait methods
+        // Default method bodies are compiled once per implementor, as `<Impl as Trait>::method`
+        if i.default.is_some() {
+            self.default_method = self
+                .cur_trait
+                .clone()
+                .map(|trait_name| (trait_name, self.cur_mod_path.len()));
+        }
         self.cur_mod_path.push_back(i.sig.ident.to_string());
+
+        // Unsafe trait methods are only code if they have a default body
+        let outer_site = self.cur_site;
+        if i.sig.unsafety.is_some() {
+            let kind = if i.default.is_some() {
+                UnsafeKind::DefaultMethod
+            } else {
+                UnsafeKind::TraitFn
+            };
+            self.push_site(kind, i.span());
+            self.enter_last_site();
+        }
+
         visit::visit_trait_item_fn(self, i);
+        self.cur_site = outer_site;
+        self.cur_mod_path.pop_back();
+        self.default_method = None;
+    }
+
+    fn visit_item_static(&mut self, i: &ItemStatic) {
+        if matches!(i.mutability, StaticMutability::Mut(_)) {
+            self.cur_mod_path.push_back(i.ident.to_string());
+            self.push_site_between(UnsafeKind::StaticMut, i.static_token.span, i.ident.span());
+            self.cur_mod_path.pop_back();
+        }
+        self.walk_initializer(&i.ident, &i.attrs, &i.expr);
+    }
+
+    fn visit_item_const(&mut self, i: &ItemConst) {
+        self.walk_initializer(&i.ident, &i.attrs, &i.expr);
+    }
+
+    fn visit_impl_item_const(&mut self, i: &ImplItemConst) {
+        self.walk_initializer(&i.ident, &i.attrs, &i.expr);
+    }
+
+    fn visit_trait_item_const(&mut self, i: &TraitItemConst) {
+        if let Some((_, expr)) = &i.default {
+            self.walk_initializer(&i.ident, &i.attrs, expr);
+        }
+    }
+
+    fn visit_item_union(&mut self, i: &ItemUnion) {
+        self.cur_mod_path.push_back(i.ident.to_string());
+        self.push_site_between(UnsafeKind::Union, i.union_token.span, i.ident.span());
         self.cur_mod_path.pop_back();
     }
 
+    fn visit_item_foreign_mod(&mut self, i: &ItemForeignMod) {
+        // Rust 2024 requires `unsafe extern`, but every extern block declares foreign items
+        let first = i
+            .unsafety
+            .map_or(i.abi.extern_token.span, |unsafety| unsafety.span);
+        let last = i
+            .abi
+            .name
+            .as_ref()
+            .map_or(i.abi.extern_token.span, LitStr::span);
+        self.push_site_between(UnsafeKind::ExternBlock, first, last);
+        visit::visit_item_foreign_mod(self, i);
+    }
+
+    fn visit_attribute(&mut self, i: &Attribute) {
+        let path = i.path();
+        if path.is_ident("unsafe")
+            || UNSAFE_ATTRIBUTES
+                .iter()
+                .any(|attribute| path.is_ident(attribute))
+        {
+            self.push_site(UnsafeKind::UnsafeAttribute, i.span());
+        }
+    }
+
+    fn visit_macro(&mut self, i: &Macro) {
+        let is_asm = i
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| ASM_MACROS.contains(&segment.ident.to_string().as_str()));
+        if is_asm {
+            self.push_op(OpCategory::InlineAsm, i.span());
+        }
+        visit::visit_macro(self, i);
+    }
+
     fn visit_impl_item_fn(&mut self, i: &ImplItemFn) {
         self.cur_mod_path.push_back(i.sig.ident.to_string());
 
         // See if this method is unsafe
+        let outer_site = self.cur_site;
         if i.sig.unsafety.is_some() {
-            let pp = fmt_mod_path(&self.cur_mod_path);
-            self.buf.push(pp);
+            let kind = if self.in_trait_impl {
+                UnsafeKind::TraitMethod
+            } else {
+                UnsafeKind::Method
+            };
+            self.push_site(kind, i.span());
+            self.enter_last_site();
         }
 
         trace!("entering method {:?}", i.sig.ident);
         visit::visit_impl_item_fn(self, i);
 
+        self.cur_site = outer_site;
         self.cur_mod_path.pop_back();
     }
 }
@@ -275,18 +1070,20 @@ fn fmt_mod_path(mod_path: &VecDeque<String>) -> String {
 /// Scan a single file for `unsafe` usage.
 #[allow(clippy::unwrap_used)]
 pub fn find_unsafe_in_file(
-    crate_name: &str,
+    origin: &FileOrigin,
     file_to_scan: &Path,
-    include_tests: bool,
 ) -> Result<UnsafeItems, ScanFileError> {
     use syn::visit::Visit;
+    let crate_name = origin.crate_name;
     trace!("in crate {}", crate_name);
     trace!("in file {:?}", file_to_scan);
     let src = std::ffi::OsString::from("src");
     let src_cpt = std::path::Component::Normal(&src);
 
     // Get the module path of the file we're in right now
-    let prefix_module_path = if file_to_scan.components().any(|c| c == src_cpt) {
+    let prefix_module_path = if let Some(module_path) = origin.module_path {
+        module_path.join("::")
+    } else if file_to_scan.components().any(|c| c == src_cpt) {
         let mut mods: Vec<String> = file_to_scan
             .components()
             .rev()
@@ -325,8 +1122,419 @@ pub fn find_unsafe_in_file(
     let syntax =
         syn::parse_file(&src).map_err(|e| ScanFileError::Syn(e, file_to_scan.to_path_buf()))?;
 
-    let mut vis = SiderophileSynVisitor::new(full_prefix, include_tests);
+    let mut decls = UnsafeDecls::default();
+    decls.visit_file(&syntax);
+    let mut vis = SiderophileSynVisitor::new(full_prefix, origin, file_to_scan, decls);
     vis.visit_file(&syntax);
 
     Ok(UnsafeItems(vis.buf))
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Scans `src` as the root file of the crate `renamed_core` and returns the items of the
+    /// unsafe sites found
+    fn scan(src: &str, extern_crates: &HashMap<String, String>, cfg: &CfgSet) -> Vec<String> {
+        let mut file = tempfile::Builder::new().suffix(".rs").tempfile().unwrap();
+        file.write_all(src.as_bytes()).unwrap();
+        let origin = FileOrigin {
+            crate_name: "renamed_core",
+            package_name: "renamedlib",
+            package_version: "0.1.0",
+            extern_crates,
+            module_path: None,
+            cfg,
+        };
+        let UnsafeItems(sites) = find_unsafe_in_file(&origin, file.path()).unwrap();
+        sites.into_iter().map(|site| site.item).collect()
+    }
+
+    #[test]
+    fn test_impls_for_renamed_dependencies() {
+        // `renamed-helper`, whose library is `helper_impl`, is known as `helper` in this crate
+        let extern_crates = HashMap::from([("helper".to_string(), "helper_impl".to_string())]);
+        let items = scan(
+            r"
+            impl Poke for helper::Cell {
+                unsafe fn poke(&self) {}
+            }
+            impl Poke for Local {
+                unsafe fn poke(&self) {}
+            }
+            ",
+            &extern_crates,
+            &CfgSet::default(),
+        );
+        assert_eq!(
+            items,
+            [
+                "<helper_impl::Cell as Poke>::poke",
+                "<renamed_core::Local as Poke>::poke"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cfg() {
+        let mut cfg = CfgSet::default();
+        cfg.insert("unix");
+        cfg.insert("feature=\"std\"");
+        let items = scan(
+            r#"
+            #[cfg(windows)]
+            unsafe fn windows_only() {}
+            #[cfg(unix)]
+            unsafe fn unix_only() {}
+            #[cfg(test)]
+            mod tests {
+                unsafe fn helper() {}
+            }
+            impl Local {
+                #[cfg(not(feature = "std"))]
+                unsafe fn no_std(&self) {}
+                #[cfg_attr(feature = "std", cfg(any()))]
+                unsafe fn cfg_attr_out(&self) {}
+            }
+            fn f() {
+                #[cfg(windows)]
+                unsafe {}
+                #[cfg(unix)]
+                unsafe {}
+            }
+            "#,
+            &HashMap::new(),
+            &cfg,
+        );
+        assert_eq!(items, ["renamed_core::unix_only", "renamed_core::f"]);
+    }
+
+    #[test]
+    fn test_impl_self_types() {
+        let extern_crates = HashMap::from([("helper".to_string(), "helper_impl".to_string())]);
+        for (self_ty, expected) in [
+            ("Local", "<renamed_core::Local as Poke>::poke"),
+            ("u8", "<u8 as Poke>::poke"),
+            ("std::vec::Vec<T>", "<alloc::vec::Vec<T> as Poke>::poke"),
+            (
+                "std::collections::BTreeMap<T, u8>",
+                "<alloc::collections::btree::map::BTreeMap<T,u8> as Poke>::poke",
+            ),
+            ("&'a T", "<&T as Poke>::poke"),
+            ("&mut [T]", "<&mut [T] as Poke>::poke"),
+            ("*const T", "<*const T as Poke>::poke"),
+            ("*mut Local", "<*mut renamed_core::Local as Poke>::poke"),
+            ("(T, u8)", "<(T,u8) as Poke>::poke"),
+            ("(T,)", "<(T,) as Poke>::poke"),
+            ("()", "<() as Poke>::poke"),
+            ("[T; N]", "<[T; N] as Poke>::poke"),
+            ("[u8; 4]", "<[u8; 4] as Poke>::poke"),
+            ("&helper::Cell", "<&helper_impl::Cell as Poke>::poke"),
+            ("dyn Trait", "<dyn renamed_core::Trait as Poke>::poke"),
+            (
+                "dyn crate::Trait + Send + 'a",
+                "<dyn renamed_core::Trait+core::marker::Send as Poke>::poke",
+            ),
+            (
+                "&(dyn std::any::Any)",
+                "<&dyn core::any::Any as Poke>::poke",
+            ),
+            ("&std::vec::Vec<T>", "<&alloc::vec::Vec<T> as Poke>::poke"),
+            (
+                "&std::collections::HashMap<T, u8>",
+                "<&std::collections::hash::map::HashMap<T,u8> as Poke>::poke",
+            ),
+        ] {
+            let items = scan(
+                &format!(
+                    "impl<'a, T, const N: usize> Poke for {self_ty} {{ unsafe fn poke(&self) {{}} }}"
+                ),
+                &extern_crates,
+                &CfgSet::default(),
+            );
+            assert_eq!(items, [expected], "{self_ty}");
+        }
+    }
+
+    #[test]
+    fn test_trait_definitions() {
+        let mut file = tempfile::Builder::new().suffix(".rs").tempfile().unwrap();
+        file.write_all(
+            br"
+            trait Poke {
+                unsafe fn declared(&self);
+                unsafe fn defaulted(&self) {}
+                fn safe_default(&self) {
+                    unsafe {}
+                    let _ = || unsafe {};
+                    fn nested() {
+                        unsafe {}
+                    }
+                }
+            }
+            ",
+        )
+        .unwrap();
+        let origin = FileOrigin {
+            crate_name: "renamed_core",
+            package_name: "renamedlib",
+            package_version: "0.1.0",
+            extern_crates: &HashMap::new(),
+            module_path: None,
+            cfg: &CfgSet::default(),
+        };
+        let UnsafeItems(sites) = find_unsafe_in_file(&origin, file.path()).unwrap();
+        let sites: Vec<(&str, UnsafeKind, Option<&str>)> = sites
+            .iter()
+            .map(|site| {
+                (
+                    site.item.as_str(),
+                    site.kind,
+                    site.default_method.as_ref().map(|default| {
+                        assert_eq!(default.trait_name, "Poke");
+                        default.item.as_str()
+                    }),
+                )
+            })
+            .collect();
+        assert_eq!(
+            sites,
+            [
+                ("renamed_core::Poke::declared", UnsafeKind::TraitFn, None),
+                (
+                    "renamed_core::Poke::defaulted",
+                    UnsafeKind::DefaultMethod,
+                    Some("defaulted")
+                ),
+                (
+                    "renamed_core::Poke::safe_default",
+                    UnsafeKind::Block,
+                    Some("safe_default")
+                ),
+                (
+                    "renamed_core::Poke::safe_default::{{closure}}",
+                    UnsafeKind::Block,
+                    Some("safe_default::{{closure}}")
+                ),
+                (
+                    "renamed_core::Poke::safe_default::nested",
+                    UnsafeKind::Block,
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsafe_constructs() {
+        let mut file = tempfile::Builder::new().suffix(".rs").tempfile().unwrap();
+        file.write_all(
+            br#"
+            unsafe impl Send for Local {}
+            unsafe trait Zeroable {}
+            static mut COUNTER: u32 = 0;
+            static TABLE: u32 = unsafe { COUNTER };
+            const CALLBACK: fn() = || unsafe {};
+            union Bits {
+                int: u32,
+                float: f32,
+            }
+            extern "C" {
+                fn abs(x: i32) -> i32;
+            }
+            unsafe extern "C" {
+                safe fn labs(x: i64) -> i64;
+            }
+            #[unsafe(no_mangle)]
+            pub extern "C" fn exported() {
+                let _ = const { unsafe { 1 } };
+            }
+            #[no_mangle]
+            pub extern "C" fn legacy_exported() {}
+            impl Local {
+                const ZERO: u32 = unsafe { 0 };
+            }
+            "#,
+        )
+        .unwrap();
+        let origin = FileOrigin {
+            crate_name: "renamed_core",
+            package_name: "renamedlib",
+            package_version: "0.1.0",
+            extern_crates: &HashMap::new(),
+            module_path: None,
+            cfg: &CfgSet::default(),
+        };
+        let UnsafeItems(sites) = find_unsafe_in_file(&origin, file.path()).unwrap();
+        let sites: Vec<(&str, UnsafeKind, usize, usize)> = sites
+            .iter()
+            .map(|site| {
+                (
+                    site.item.as_str(),
+                    site.kind,
+                    site.span.start_line,
+                    site.span.end_line,
+                )
+            })
+            .collect();
+        assert_eq!(
+            sites,
+            [
+                (
+                    "<renamed_core::Local as Send>",
+                    UnsafeKind::UnsafeImpl,
+                    2,
+                    2
+                ),
+                ("renamed_core::Zeroable", UnsafeKind::UnsafeTrait, 3, 3),
+                ("renamed_core::COUNTER", UnsafeKind::StaticMut, 4, 4),
+                ("renamed_core::TABLE", UnsafeKind::InitializerBlock, 5, 5),
+                (
+                    "renamed_core::CALLBACK::{{closure}}",
+                    UnsafeKind::Block,
+                    6,
+                    6
+                ),
+                ("renamed_core::Bits", UnsafeKind::Union, 7, 7),
+                ("renamed_core", UnsafeKind::ExternBlock, 11, 11),
+                ("renamed_core", UnsafeKind::ExternBlock, 14, 14),
+                (
+                    "renamed_core::exported",
+                    UnsafeKind::UnsafeAttribute,
+                    17,
+                    17
+                ),
+                (
+                    "renamed_core::exported",
+                    UnsafeKind::InitializerBlock,
+                    19,
+                    19
+                ),
+                (
+                    "renamed_core::legacy_exported",
+                    UnsafeKind::UnsafeAttribute,
+                    21,
+                    21
+                ),
+                (
+                    "renamed_core::Local::ZERO",
+                    UnsafeKind::InitializerBlock,
+                    24,
+                    24
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operations() {
+        let mut file = tempfile::Builder::new().suffix(".rs").tempfile().unwrap();
+        file.write_all(
+            br#"
+            extern "C" {
+                fn abs(x: i32) -> i32;
+                static ERRNO: i32;
+            }
+            static mut COUNTER: u32 = 0;
+            union Bits {
+                int: u32,
+                float: f32,
+            }
+            unsafe fn helper() {}
+            fn f(p: *const u8, v: &mut Vec<u8>, mut bits: Bits, c: &Cursor) {
+                unsafe {
+                    let _ = *p;
+                    let _ = *(p as *mut u8);
+                    let _ = *v.as_ptr();
+                    helper();
+                    abs(-1);
+                    zstd_sys::ZSTD_versionNumber();
+                    let _ = ERRNO;
+                    let _: u32 = std::mem::transmute(1.0f32);
+                    COUNTER += 1;
+                    let _ = bits.int;
+                    bits.float = 1.0;
+                    core::arch::asm!("nop");
+                    let _ = v.get_unchecked(0);
+                    v.set_len(0);
+                    let _ = v.as_ptr().offset(1);
+                    let _ = c.offset(1);
+                }
+            }
+            unsafe fn g(p: *mut u8) {
+                p.write_volatile(0);
+                let _ = unsafe { std::ptr::read(p) };
+            }
+            "#,
+        )
+        .unwrap();
+        let origin = FileOrigin {
+            crate_name: "renamed_core",
+            package_name: "renamedlib",
+            package_version: "0.1.0",
+            extern_crates: &HashMap::new(),
+            module_path: None,
+            cfg: &CfgSet::default(),
+        };
+        let UnsafeItems(sites) = find_unsafe_in_file(&origin, file.path()).unwrap();
+        let sites: Vec<(&str, Vec<OpCategory>)> = sites
+            .iter()
+            .filter(|site| site.kind.has_code())
+            .map(|site| {
+                (
+                    site.item.as_str(),
+                    site.operations.iter().map(|op| op.category).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            sites,
+            [
+                ("renamed_core::helper", vec![]),
+                (
+                    "renamed_core::f",
+                    vec![
+                        OpCategory::UnknownDeref,
+                        OpCategory::RawPointerDeref,
+                        OpCategory::RawPointerDeref,
+                        OpCategory::UnsafeCall,
+                        OpCategory::FfiCall,
+                        OpCategory::FfiCall,
+                        OpCategory::StaticMutAccess,
+                        OpCategory::Transmute,
+                        OpCategory::StaticMutAccess,
+                        OpCategory::UnionFieldRead,
+                        OpCategory::InlineAsm,
+                        OpCategory::UncheckedCall,
+                        OpCategory::UncheckedCall,
+                        OpCategory::UnsafeCall,
+                    ]
+                ),
+                ("renamed_core::g", vec![OpCategory::UnsafeCall]),
+                ("renamed_core::g", vec![OpCategory::UnsafeCall]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_test_functions() {
+        let src = r"
+            #[test]
+            fn poke_test() {
+                unsafe {}
+            }
+            ";
+        let mut cfg = CfgSet::default();
+        assert!(scan(src, &HashMap::new(), &cfg).is_empty());
+        // Test harnesses are compiled with `--test`, which sets `cfg(test)`
+        cfg.insert("test");
+        assert_eq!(
+            scan(src, &HashMap::new(), &cfg),
+            ["renamed_core::poke_test"]
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Evaluates `#[cfg]` and `#[cfg_attr]` attributes against the configuration a crate is compiled
+//! with, so that code which is not built is not scanned
+
+use std::{collections::HashSet, env, io, process::Command};
+
+use syn::{
+    ext::IdentExt,
+    parenthesized,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    Attribute, Ident, LitStr, Meta, Token,
+};
+
+/// A configuration predicate, as in `#[cfg(all(unix, feature = "std"))]`
+#[derive(Debug, PartialEq, Eq)]
+enum CfgExpr {
+    Name(String),
+    KeyValue(String, String),
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Not(Box<CfgExpr>),
+}
+
+impl Parse for CfgExpr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = Ident::parse_any(input)?.to_string();
+        if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            return Ok(Self::KeyValue(name, input.parse::<LitStr>()?.value()));
+        }
+        if !input.peek(syn::token::Paren) {
+            return Ok(Self::Name(name));
+        }
+        let content;
+        parenthesized!(content in input);
+        let mut exprs: Vec<Self> = Punctuated::<Self, Token![,]>::parse_terminated(&content)?
+            .into_iter()
+            .collect();
+        match name.as_str() {
+            "all" => Ok(Self::All(exprs)),
+            "any" => Ok(Self::Any(exprs)),
+            "not" if exprs.len() == 1 => Ok(Self::Not(Box::new(exprs.remove(0)))),
+            _ => Err(input.error("unknown configuration predicate")),
+        }
+    }
+}
+
+/// The `cfg_attr(predicate, attr, ...)` attribute
+struct CfgAttr {
+    predicate: CfgExpr,
+    attrs: Vec<Meta>,
+}
+
+impl Parse for CfgAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let predicate = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let attrs = Punctuated::<Meta, Token![,]>::parse_terminated(input)?
+            .into_iter()
+            .collect();
+        Ok(Self { predicate, attrs })
+    }
+}
+
+/// The configuration options a crate is compiled with, like `unix`, `target_os = "linux"` or
+/// `feature = "std"`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CfgSet {
+    options: HashSet<(String, Option<String>)>,
+    /// Whether the options of the target are not known, in which case every `cfg` holds
+    unknown: bool,
+}
+
+impl CfgSet {
+    /// A configuration whose target options could not be found, under which all code counts as
+    /// compiled, so that nothing is hidden by mistake
+    pub fn unknown() -> Self {
+        Self {
+            unknown: true,
+            ..Self::default()
+        }
+    }
+
+    /// The options rustc sets for `target`, or the host if it is `None`, like `unix` and
+    /// `debug_assertions`, as they are with the flags in `callgraph_gen::RUSTFLAGS`. Like cargo,
+    /// this runs the rustc in the `RUSTC` environment variable, if it is set.
+    pub fn rustc(target: Option<&str>) -> io::Result<Self> {
+        let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        let mut command = Command::new(rustc);
+        command.args(["--print", "cfg", "-C", "opt-level=0"]);
+        if let Some(target) = target {
+            command.args(["--target", target]);
+        }
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "`rustc --print cfg` failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let mut cfg = Self::default();
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            cfg.insert(line);
+        }
+        Ok(cfg)
+    }
+
+    /// Adds an option written like `--cfg` takes it, as in `test` or `feature="std"`
+    pub fn insert(&mut self, option: &str) {
+        let option = match option.split_once('=') {
+            Some((name, value)) => (
+                name.trim().to_string(),
+                Some(value.trim().trim_matches('"').to_string()),
+            ),
+            None => (option.trim().to_string(), None),
+        };
+        self.options.insert(option);
+    }
+
+    /// Whether the option `name`, without a value, is set, as `test` is for test harnesses
+    pub fn is_set(&self, name: &str) -> bool {
+        self.options.contains(&(name.to_string(), None))
+    }
+
+    fn eval(&self, expr: &CfgExpr) -> bool {
+        match expr {
+            CfgExpr::Name(name) => {
+                name == "true" || (name != "false" && self.options.contains(&(name.clone(), None)))
+            }
+            CfgExpr::KeyValue(name, value) => {
+                self.options.contains(&(name.clone(), Some(value.clone())))
+            }
+            CfgExpr::All(exprs) => exprs.iter().all(|expr| self.eval(expr)),
+            CfgExpr::Any(exprs) => exprs.iter().any(|expr| self.eval(expr)),
+            CfgExpr::Not(expr) => !self.eval(expr),
+        }
+    }
+
+    /// The attributes that apply with this configuration: `cfg_attr`s are replaced by the
+    /// attributes they carry if their predicate holds, and dropped otherwise
+    pub fn expand_attrs(&self, attrs: &[Attribute]) -> Vec<Meta> {
+        let mut expanded = Vec::new();
+        let mut pending: Vec<Meta> = attrs.iter().rev().map(|attr| attr.meta.clone()).collect();
+        while let Some(meta) = pending.pop() {
+            match &meta {
+                Meta::List(list) if list.path.is_ident("cfg_attr") => {
+                    // An attribute that cannot be parsed is kept out, like rustc would reject it
+                    if let Ok(cfg_attr) = list.parse_args::<CfgAttr>() {
+                        if self.unknown || self.eval(&cfg_attr.predicate) {
+                            pending.extend(cfg_attr.attrs.into_iter().rev());
+                        }
+                    }
+                }
+                _ => expanded.push(meta),
+            }
+        }
+        expanded
+    }
+
+    /// Whether an item with the given attributes is compiled with this configuration. Malformed
+    /// `cfg`s count as enabled, so that nothing is hidden by mistake.
+    pub fn is_enabled(&self, attrs: &[Attribute]) -> bool {
+        self.unknown
+            || self.expand_attrs(attrs).iter().all(|meta| match meta {
+                Meta::List(list) if list.path.is_ident("cfg") => list
+                    .parse_args::<CfgExpr>()
+                    .map_or(true, |expr| self.eval(&expr)),
+                _ => true,
+            })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn attrs(src: &str) -> Vec<Attribute> {
+        syn::parse_str::<syn::ItemFn>(&format!("{src} fn f() {{}}"))
+            .unwrap()
+            .attrs
+    }
+
+    #[test]
+    fn test_cfg_evaluation() {
+        let mut cfg = CfgSet::default();
+        for option in ["unix", "target_os=\"linux\"", "feature=\"std\""] {
+            cfg.insert(option);
+        }
+        for (src, enabled) in [
+            ("", true),
+            ("#[cfg(unix)]", true),
+            ("#[cfg(windows)]", false),
+            ("#[cfg(test)]", false),
+            ("#[cfg(target_os = \"linux\")]", true),
+            ("#[cfg(target_os = \"macos\")]", false),
+            ("#[cfg(all(unix, feature = \"std\"))]", true),
+            ("#[cfg(all(unix, feature = \"alloc\"))]", false),
+            ("#[cfg(any(windows, feature = \"std\"))]", true),
+            ("#[cfg(not(test))]", true),
+            ("#[cfg(unix)] #[cfg(test)]", false),
+            ("#[cfg_attr(unix, cfg(test))]", false),
+            ("#[cfg_attr(windows, cfg(test))]", true),
+            (
+                "#[cfg_attr(unix, cfg_attr(not(test), cfg(feature = \"alloc\")))]",
+                false,
+            ),
+        ] {
+            assert_eq!(cfg.is_enabled(&attrs(src)), enabled, "{src}");
+        }
+    }
+
+    #[test]
+    fn test_expand_cfg_attr() {
+        let mut cfg = CfgSet::default();
+        cfg.insert("unix");
+        let expanded = cfg.expand_attrs(&attrs(
+            "#[inline] #[cfg_attr(unix, path = \"unix.rs\", cold)] #[cfg_attr(windows, path = \"windows.rs\")]",
+        ));
+        let names: Vec<String> = expanded
+            .iter()
+            .map(|meta| meta.path().get_ident().unwrap().to_string())
+            .collect();
+        assert_eq!(names, ["inline", "path", "cold"]);
+    }
+
+    #[test]
+    fn test_unknown_cfg() {
+        let mut cfg = CfgSet::unknown();
+        cfg.insert("feature=\"std\"");
+        for src in [
+            "#[cfg(unix)]",
+            "#[cfg(windows)]",
+            "#[cfg(not(feature = \"std\"))]",
+            "#[cfg_attr(windows, cfg(test))]",
+        ] {
+            assert!(cfg.is_enabled(&attrs(src)), "{src}");
+        }
+        assert_eq!(
+            cfg.expand_attrs(&attrs("#[cfg_attr(windows, path = \"windows.rs\")]"))
+                .len(),
+            1
+        );
+    }
+}
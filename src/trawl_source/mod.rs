@@ -1,4 +1,6 @@
 mod ast_walker;
+mod cfg;
+mod module_tree;
 
 use std::{
     collections::{HashMap, HashSet},
@@ -15,14 +17,19 @@ use cargo::{
         compiler::{CompileMode, Executor, Unit},
         manifest::TargetKind,
         package::PackageSet,
+        resolver::Resolve,
         Package, PackageId, Target, Workspace,
     },
-    ops::CompileOptions,
+    ops::{CompileFilter, CompileOptions},
     util::CargoResult,
 };
 use cargo_util::{paths, ProcessBuilder};
 use walkdir::{self, WalkDir};
 
+use crate::unsafe_site::UnsafeSite;
+
+use cfg::CfgSet;
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum RsResolveError {
@@ -106,9 +113,16 @@ impl RsFile {
     }
 }
 
+/// Finds the .rs files of the package in `dir`, skipping the directories of packages nested in it
+/// and target directories, which cargo marks with a `CACHEDIR.TAG`
 #[allow(clippy::expect_used)]
 pub fn find_rs_files_in_dir(dir: &Path) -> impl Iterator<Item = PathBuf> {
-    let walker = WalkDir::new(dir).into_iter();
+    let walker = WalkDir::new(dir).into_iter().filter_entry(|entry| {
+        entry.depth() == 0
+            || !entry.file_type().is_dir()
+            || !(entry.path().join("Cargo.toml").exists()
+                || entry.path().join("CACHEDIR.TAG").exists())
+    });
     walker.filter_map(|entry| {
         let entry = entry.expect("walkdir error."); // TODO: Return result.
         if !is_file_with_ext(&entry, "rs") {
@@ -169,14 +183,84 @@ const fn into_rs_code_file(kind: &TargetKind, path: PathBuf) -> RsFile {
     }
 }
 
-fn find_rs_files_in_packages<'a>(
-    packs: &'a [&Package],
-) -> impl Iterator<Item = (PackageId, RsFile)> + 'a {
-    packs.iter().flat_map(|pack| {
-        find_rs_files_in_package(pack)
-            .into_iter()
-            .map(move |path| (pack.package_id(), path))
-    })
+/// Maps files in module trees to the name of their crate and the module they are
+type ModuleFiles = HashMap<PathBuf, (String, module_tree::ModuleFile)>;
+
+/// Maps every file in the module tree of a target of `pack` to the name of the target's crate and
+/// the module the file is. A file used by several targets is attributed to the library, if it is
+/// one of them. `out_dir` is the `OUT_DIR` of the package's build script, if it has one.
+fn package_modules(pack: &Package, out_dir: Option<&Path>, cfg: &CfgSet) -> ModuleFiles {
+    let mut targets: Vec<&Target> = pack.targets().iter().collect();
+    targets.sort_by_key(|target| (!target.is_lib(), !target.is_bin()));
+    let mut modules = HashMap::new();
+    for target in targets {
+        let Some(root) = target.src_path().path() else {
+            continue;
+        };
+        for (file, module) in module_tree::module_files(root, out_dir, cfg) {
+            modules
+                .entry(file)
+                .or_insert_with(|| (target.crate_name(), module));
+        }
+    }
+    modules
+}
+
+/// The name of the crate a file outside of any module tree is guessed to be compiled into. The
+/// root file of a binary belongs to that binary, every other file to the library of the package,
+/// or to its first binary if it has no library. Targets can be renamed in `Cargo.toml`, so this
+/// is not always the package name.
+fn crate_name_of(pack: &Package, file: &RsFile) -> String {
+    let targets = pack.targets();
+    let bin_root = match file {
+        RsFile::BinRoot(path) => targets.iter().find(|target| {
+            target.is_bin()
+                && target
+                    .src_path()
+                    .path()
+                    .and_then(|src| src.canonicalize().ok())
+                    .is_some_and(|src| &src == path)
+        }),
+        _ => None,
+    };
+    bin_root
+        .or_else(|| targets.iter().find(|target| target.is_lib()))
+        .or_else(|| targets.iter().find(|target| target.is_bin()))
+        .map_or_else(
+            || pack.name().as_str().replace('-', "_"),
+            Target::crate_name,
+        )
+}
+
+/// Maps the names the dependencies of `pack` are known by in its source, which a
+/// `foo = { package = "bar" }` entry in `Cargo.toml` can change, to their crate names
+fn extern_crate_names(
+    resolve: &Resolve,
+    packs: &[&Package],
+    pack: &Package,
+) -> HashMap<String, String> {
+    let mut names = HashMap::new();
+    for (dep_id, _) in resolve.deps(pack.package_id()) {
+        let lib = packs
+            .iter()
+            .find(|dep| dep.package_id() == dep_id)
+            .and_then(|dep| dep.targets().iter().find(|target| target.is_lib()));
+        let Some(lib) = lib else {
+            continue;
+        };
+        match resolve.extern_crate_name_and_dep_name(pack.package_id(), dep_id, lib) {
+            Ok((extern_name, _)) => {
+                names.insert(extern_name.to_string(), lib.crate_name());
+            }
+            Err(e) => warn!(
+                "Could not name dependency {} of {}: {}",
+                dep_id,
+                pack.name(),
+                e
+            ),
+        }
+    }
+    names
 }
 
 /// This is mostly `PackageSet::get_many`. The only difference is that we don't panic when
@@ -205,18 +289,83 @@ fn get_many<'a>(
     pkgs
 }
 
+/// Scans the file at `path` once for every configuration its crate is compiled with, as told by
+/// `origins`. A site compiled with several of them is found once, with the operations found in
+/// any of them.
+fn find_unsafe_in_file_per_cfg(
+    origins: &[ast_walker::FileOrigin],
+    path: &Path,
+) -> Result<Vec<UnsafeSite>, ast_walker::ScanFileError> {
+    let mut sites: Vec<UnsafeSite> = Vec::new();
+    for origin in origins {
+        let ast_walker::UnsafeItems(found) = ast_walker::find_unsafe_in_file(origin, path)?;
+        for site in found {
+            let known = sites
+                .iter_mut()
+                .find(|known| known.kind == site.kind && known.span == site.span);
+            match known {
+                Some(known) => {
+                    for operation in site.operations {
+                        if !known.operations.contains(&operation) {
+                            known.operations.push(operation);
+                        }
+                    }
+                    known.operations.sort_by_key(|operation| operation.span);
+                }
+                None => sites.push(site),
+            }
+        }
+    }
+    sites.sort_by_key(|site| site.span);
+    Ok(sites)
+}
+
 /// Finds and outputs all unsafe things to the given file
 #[allow(clippy::panic)]
 pub fn find_unsafe_in_packages(
     packs: &PackageSet,
+    resolve: &Resolve,
+    out_dirs: &HashMap<PackageId, PathBuf>,
+    cfgs: &HashMap<PackageId, Vec<CfgSet>>,
     mut rs_files_used: HashMap<PathBuf, u32>,
     allow_partial_results: bool,
-    include_tests: bool,
-) -> (HashMap<PathBuf, u32>, Vec<String>) {
+) -> (HashMap<PathBuf, u32>, Vec<UnsafeSite>) {
     let packs = get_many(packs, packs.package_ids());
-    let pack_code_files = find_rs_files_in_packages(&packs);
+    let default_cfgs = vec![CfgSet::default()];
+    let cfgs_of = |pack_id: PackageId| cfgs.get(&pack_id).unwrap_or(&default_cfgs);
+    // The module trees of every package, one for each of its configurations
+    let modules: HashMap<PackageId, Vec<ModuleFiles>> = packs
+        .iter()
+        .map(|pack| {
+            let out_dir = out_dirs.get(&pack.package_id()).map(PathBuf::as_path);
+            let pack_modules = cfgs_of(pack.package_id())
+                .iter()
+                .map(|cfg| package_modules(pack, out_dir, cfg))
+                .collect();
+            (pack.package_id(), pack_modules)
+        })
+        .collect();
+    let mut pack_code_files = Vec::new();
+    for pack in &packs {
+        let files = find_rs_files_in_package(pack);
+        let listed: HashSet<&PathBuf> = files.iter().map(RsFile::as_path_buf).collect();
+        // Code generated by build scripts is not in the package, so it is only found through the
+        // `include!`s in the package's module trees
+        let generated: HashSet<&PathBuf> = modules[&pack.package_id()]
+            .iter()
+            .flat_map(HashMap::keys)
+            .filter(|file| !listed.contains(file))
+            .collect();
+        let generated: Vec<RsFile> = generated
+            .into_iter()
+            .map(|file| RsFile::Other(file.clone()))
+            .collect();
+        pack_code_files.extend(files.into_iter().chain(generated).map(|file| (*pack, file)));
+    }
+    let mut extern_crates: HashMap<PackageId, HashMap<String, String>> = HashMap::new();
     let mut tainted_things = vec![];
-    for (pack_id, rs_code_file) in pack_code_files {
+    for (pack, rs_code_file) in pack_code_files {
+        let pack_id = pack.package_id();
         let p = rs_code_file.as_path_buf();
 
         // This .rs file path was found by intercepting rustc arguments or by parsing the .d files
@@ -228,9 +377,44 @@ pub fn find_unsafe_in_packages(
             *c += 1;
         }
 
-        let crate_name = pack_id.name().as_str().replace('-', "_");
-        match ast_walker::find_unsafe_in_file(&crate_name, p, include_tests) {
-            Ok(ast_walker::UnsafeItems(items)) => {
+        // The configurations the file is compiled with, with where it is in the module tree of
+        // each
+        let compiled: Vec<(&CfgSet, Option<&(String, module_tree::ModuleFile)>)> = cfgs_of(pack_id)
+            .iter()
+            .zip(&modules[&pack_id])
+            .map(|(cfg, pack_modules)| (cfg, pack_modules.get(p)))
+            .filter(|(_, module)| module.is_none_or(|(_, module)| module.enabled))
+            .collect();
+        if compiled.is_empty() {
+            continue;
+        }
+        let crate_names: Vec<String> = compiled
+            .iter()
+            .map(|(_, module)| {
+                module.map_or_else(
+                    || crate_name_of(pack, &rs_code_file),
+                    |(crate_name, _)| crate_name.clone(),
+                )
+            })
+            .collect();
+        let package_version = pack_id.version().to_string();
+        let pack_extern_crates = &*extern_crates
+            .entry(pack_id)
+            .or_insert_with(|| extern_crate_names(resolve, &packs, pack));
+        let origins: Vec<ast_walker::FileOrigin> = compiled
+            .iter()
+            .zip(&crate_names)
+            .map(|((cfg, module), crate_name)| ast_walker::FileOrigin {
+                crate_name,
+                extern_crates: pack_extern_crates,
+                module_path: module.map(|(_, module)| module.path.as_slice()),
+                package_name: pack_id.name().as_str(),
+                package_version: &package_version,
+                cfg,
+            })
+            .collect();
+        match find_unsafe_in_file_per_cfg(&origins, p) {
+            Ok(items) => {
                 // Output unsafe items as we go
                 tainted_things.extend(items);
             }
@@ -252,11 +436,11 @@ pub fn find_unsafe_in_packages(
 }
 
 /// Trigger a `cargo build` and listen to the cargo/rustc communication to
-/// figure out which source files were used by the build.
+/// figure out which source files were used by the build, and which units were compiled.
 pub fn resolve_rs_file_deps(
     copt: &CompileOptions,
     ws: &Workspace,
-) -> anyhow::Result<HashMap<PathBuf, u32>> {
+) -> anyhow::Result<(HashMap<PathBuf, u32>, Vec<CompiledUnit>)> {
     let config = ws.config();
     set_var("RUSTFLAGS", crate::callgraph_gen::RUSTFLAGS);
     let inner_arc = Arc::new(Mutex::new(CustomExecutorInnerContext::default()));
@@ -272,9 +456,9 @@ pub fn resolve_rs_file_deps(
     }
     let ws_root = ws.root().to_path_buf();
     let inner_mutex = Arc::try_unwrap(inner_arc).map_err(|_| RsResolveError::ArcUnwrap())?;
-    let (rs_files, out_dir_args) = {
+    let (rs_files, out_dir_args, units) = {
         let ctx = inner_mutex.into_inner()?;
-        (ctx.rs_file_args, ctx.out_dir_args)
+        (ctx.rs_file_args, ctx.out_dir_args, ctx.units)
     };
     let mut hm = HashMap::<PathBuf, u32>::new();
     for out_dir in out_dir_args {
@@ -304,7 +488,7 @@ pub fn resolve_rs_file_deps(
         // rs_files must already be canonicalized
         hm.insert(pb, 0);
     }
-    Ok(hm)
+    Ok((hm, units))
 }
 
 /// Copy-pasted (almost) from the private module `cargo::core::compiler::fingerprint`.
@@ -348,6 +532,36 @@ struct CustomExecutorInnerContext {
     /// Investigate if this needs to be intercepted like this or if it can be
     /// looked up in a nicer way.
     out_dir_args: HashSet<PathBuf>,
+
+    /// Every rustc invocation of the build
+    units: Vec<CompiledUnit>,
+}
+
+/// A crate compiled by rustc during the build
+#[derive(Debug, Clone)]
+pub struct CompiledUnit {
+    pub package_id: PackageId,
+    pub target: Target,
+    pub mode: CompileMode,
+    /// The `--crate-name` passed to rustc
+    pub crate_name: String,
+    /// The `-C extra-filename` passed to rustc, empty if there is none
+    pub extra_filename: String,
+    pub out_dir: PathBuf,
+    /// The `OUT_DIR` of the package's build script, if it has one
+    pub build_out_dir: Option<PathBuf>,
+    /// The `--cfg` options passed to rustc, like `feature="std"`, and `test` for test harnesses
+    pub cfgs: Vec<String>,
+    /// The `--target` passed to rustc, if the unit is not built for the host
+    pub target_triple: Option<String>,
+}
+
+impl CompiledUnit {
+    /// Where rustc writes the bitcode of the unit, since `RUSTFLAGS` contains `--emit=llvm-bc`
+    pub fn bitcode_path(&self) -> PathBuf {
+        self.out_dir
+            .join(format!("{}{}.bc", self.crate_name, self.extra_filename))
+    }
 }
 
 use std::sync::PoisonError;
@@ -375,6 +589,7 @@ use std::fmt;
 enum CustomExecutorError {
     OutDirKeyMissing(String),
     OutDirValueMissing(String),
+    CrateNameMissing(String),
     InnerContextMutex(String),
     Io(io::Error, PathBuf),
 }
@@ -396,9 +611,9 @@ impl Executor for CustomExecutor {
     fn exec(
         &self,
         command: &ProcessBuilder,
-        _id: PackageId,
-        _target: &Target,
-        _mode: CompileMode,
+        id: PackageId,
+        target: &Target,
+        mode: CompileMode,
         _on_stdout_line: &mut dyn FnMut(&str) -> CargoResult<()>,
         _on_stderr_line: &mut dyn FnMut(&str) -> CargoResult<()>,
     ) -> CargoResult<()> {
@@ -412,6 +627,31 @@ impl Executor for CustomExecutor {
             .get(out_dir_key_idx + 1)
             .ok_or_else(|| CustomExecutorError::OutDirValueMissing(command.to_string()))
             .map(PathBuf::from)?;
+        let crate_name = args
+            .iter()
+            .position(|s| *s == "--crate-name")
+            .and_then(|idx| args.get(idx + 1))
+            .ok_or_else(|| CustomExecutorError::CrateNameMissing(command.to_string()))?
+            .to_string_lossy()
+            .to_string();
+        let extra_filename = args
+            .iter()
+            .filter_map(|s| s.to_str())
+            .find_map(|s| s.strip_prefix("extra-filename="))
+            .unwrap_or_default()
+            .to_string();
+        let mut cfgs: Vec<String> = args
+            .windows(2)
+            .filter(|pair| *pair[0] == "--cfg")
+            .map(|pair| pair[1].to_string_lossy().to_string())
+            .collect();
+        if args.iter().any(|s| *s == "--test") {
+            cfgs.push("test".to_string());
+        }
+        let target_triple = args
+            .windows(2)
+            .find(|pair| *pair[0] == "--target")
+            .map(|pair| pair[1].to_string_lossy().to_string());
 
         // This can be different from the cwd used to launch the wrapping cargo
         // plugin. Discovered while fixing
@@ -437,6 +677,17 @@ impl Executor for CustomExecutor {
                     .map_err(|e| CustomExecutorError::Io(e, raw_path))?;
                 ctx.rs_file_args.insert(p);
             }
+            ctx.units.push(CompiledUnit {
+                package_id: id,
+                target: target.clone(),
+                mode,
+                crate_name,
+                extra_filename,
+                out_dir: out_dir.clone(),
+                build_out_dir: command.get_env("OUT_DIR").map(PathBuf::from),
+                cfgs,
+                target_triple,
+            });
             ctx.out_dir_args.insert(out_dir);
         }
         command.exec()?;
@@ -450,38 +701,166 @@ impl Executor for CustomExecutor {
     }
 }
 
+/// The unsafe code found in the sources of the build
+pub struct Tainted {
+    pub unsafe_sites: Vec<UnsafeSite>,
+
+    /// The canonicalized paths of all .rs files used by the build
+    pub compiled_files: HashSet<PathBuf>,
+
+    /// Every crate compiled by the build
+    pub units: Vec<CompiledUnit>,
+}
+
+/// The options rustc sets for `target`, or the host if it is `None`. If rustc cannot tell, they
+/// are unknown, and all code is scanned as if it were compiled.
+fn target_cfg(target: Option<&str>) -> CfgSet {
+    CfgSet::rustc(target).unwrap_or_else(|e| {
+        warn!(
+            "Could not get the cfg options of {} from rustc, scanning code regardless of cfgs: {}",
+            target.unwrap_or("the host"),
+            e
+        );
+        CfgSet::unknown()
+    })
+}
+
+/// The configurations each package is compiled with, one for every unit of the package that is
+/// built differently: the options of the unit's target, and those cargo passes to rustc, such as
+/// its enabled features and `test` for test harnesses. Packages no unit builds get the host's.
+fn package_cfgs(packages: &PackageSet, units: &[CompiledUnit]) -> HashMap<PackageId, Vec<CfgSet>> {
+    let targets: HashSet<Option<&str>> = units
+        .iter()
+        .map(|unit| unit.target_triple.as_deref())
+        .chain([None])
+        .collect();
+    let target_cfgs: HashMap<Option<&str>, CfgSet> = targets
+        .into_iter()
+        .map(|target| (target, target_cfg(target)))
+        .collect();
+    let mut cfgs: HashMap<PackageId, Vec<CfgSet>> = HashMap::new();
+    for unit in units.iter().filter(|unit| !unit.target.is_custom_build()) {
+        let mut cfg = target_cfgs[&unit.target_triple.as_deref()].clone();
+        for option in &unit.cfgs {
+            cfg.insert(option);
+        }
+        let package_cfgs = cfgs.entry(unit.package_id).or_default();
+        if !package_cfgs.contains(&cfg) {
+            package_cfgs.push(cfg);
+        }
+    }
+    for id in packages.package_ids() {
+        cfgs.entry(id)
+            .or_insert_with(|| vec![target_cfgs[&None].clone()]);
+    }
+    cfgs
+}
+
+/// Builds the targets selected by `filter` in `mode`, which is `CompileMode::Test` to build them
+/// as test harnesses like `cargo test` does, and scans the sources of the build for unsafe code
 pub fn get_tainted(
     config: &cargo::Config,
     workspace: &cargo::core::Workspace,
     _package: &Option<String>,
-    include_tests: bool,
-) -> anyhow::Result<Vec<String>> {
-    let (packages, _resolve) = cargo::ops::resolve_ws(workspace)?;
+    mode: CompileMode,
+    filter: CompileFilter,
+) -> anyhow::Result<Tainted> {
+    let (packages, resolve) = cargo::ops::resolve_ws(workspace)?;
 
-    let copt = CompileOptions::new(config, CompileMode::Build)?;
-    let rs_files_used_in_compilation = resolve_rs_file_deps(&copt, workspace)?;
+    let mut copt = CompileOptions::new(config, mode)?;
+    copt.filter = filter;
+    let (rs_files_used_in_compilation, units) = resolve_rs_file_deps(&copt, workspace)?;
 
     let allow_partial_results = true;
 
+    let out_dirs: HashMap<PackageId, PathBuf> = units
+        .iter()
+        .filter_map(|unit| Some((unit.package_id, unit.build_out_dir.clone()?)))
+        .collect();
+    let cfgs = package_cfgs(&packages, &units);
     let (rs_files_scanned, tainted_things) = find_unsafe_in_packages(
         &packages,
+        &resolve,
+        &out_dirs,
+        &cfgs,
         rs_files_used_in_compilation,
         allow_partial_results,
-        include_tests,
     );
 
     rs_files_scanned
         .iter()
         .filter(|(_k, v)| **v == 0)
         .for_each(|(k, _v)| {
-            // TODO: Ivestigate if this is related to code generated by build
-            // scripts and/or macros. Some of the warnings of this kind is
-            // printed for files somewhere under the "target" directory.
+            // Code generated by build scripts is scanned when it is `include!`d
+            // from `OUT_DIR`. These are files pulled in some other way, e.g. by
+            // a procedural macro.
             // TODO: Find out if we can lookup PackageId associated with each
             // `.rs` file used by the build, including the file paths extracted
             // from `.d` dep files.
             warn!("Dependency file was never scanned: {}", k.display());
         });
 
-    Ok(tainted_things)
+    Ok(Tainted {
+        unsafe_sites: tainted_things,
+        compiled_files: rs_files_scanned.into_keys().collect(),
+        units,
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_sites_of_every_unit() {
+        let mut file = tempfile::Builder::new().suffix(".rs").tempfile().unwrap();
+        file.write_all(
+            br"
+            pub fn poke(p: *mut u8) {
+                #[cfg(not(test))]
+                unsafe { *p = 0; }
+                #[cfg(test)]
+                unsafe { *p = 1; }
+                unsafe {
+                    #[cfg(test)]
+                    std::ptr::read(p);
+                    *p = 2;
+                }
+            }
+            ",
+        )
+        .unwrap();
+        // With `--include-tests`, the library is built both as itself and as a test harness
+        let mut lib = CfgSet::default();
+        lib.insert("feature=\"std\"");
+        let mut harness = lib.clone();
+        harness.insert("test");
+        let extern_crates = HashMap::new();
+        let origin = |cfg| ast_walker::FileOrigin {
+            crate_name: "poke",
+            package_name: "poke",
+            package_version: "0.1.0",
+            extern_crates: &extern_crates,
+            module_path: Some(&[]),
+            cfg,
+        };
+
+        let sites =
+            find_unsafe_in_file_per_cfg(&[origin(&lib), origin(&harness)], file.path()).unwrap();
+        let lines: Vec<usize> = sites.iter().map(|site| site.span.start_line).collect();
+        assert_eq!(lines, [3, 5, 7]);
+        let operations: Vec<usize> = sites.iter().map(|site| site.operations.len()).collect();
+        assert_eq!(operations, [1, 1, 2]);
+        let harness_sites = find_unsafe_in_file_per_cfg(&[origin(&harness)], file.path()).unwrap();
+        assert_eq!(harness_sites.len(), 2);
+    }
+
+    #[test]
+    fn test_cfgs_of_unknown_target() {
+        assert_eq!(target_cfg(Some("no-such-target")), CfgSet::unknown());
+        assert_ne!(target_cfg(None), CfgSet::unknown());
+    }
 }
@@ -0,0 +1,368 @@
+//! Finds which module every file of a crate is, by following its `mod` declarations from the
+//! crate root the way rustc does, including `#[path]` attributes and files pulled in with
+//! `include!`
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use syn::{
+    punctuated::Punctuated, visit, Expr, ItemFn, ItemMacro, ItemMod, Lit, LitStr, Macro, Meta,
+    Token,
+};
+
+use super::cfg::CfgSet;
+
+/// The module a file of a crate is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleFile {
+    /// The path of the module inside its crate, empty for the crate root
+    pub path: Vec<String>,
+    /// Whether the module is compiled, that is the `cfg`s of the module and of the modules it is
+    /// in all hold
+    pub enabled: bool,
+}
+
+/// Walks the items of one file, loading the files of the modules it declares
+struct ModuleWalker<'a> {
+    files: &'a mut HashMap<PathBuf, ModuleFile>,
+    cfg: &'a CfgSet,
+    /// The `OUT_DIR` of the package's build script, if it has one
+    out_dir: Option<&'a Path>,
+    /// The directory of the file being walked, which `include!` paths are relative to
+    file_dir: PathBuf,
+    /// The directory the files of modules declared here are looked up in
+    dir: PathBuf,
+    /// For a file like `foo.rs`, rather than `mod.rs`, the name of its module, which the
+    /// directory of its submodules is named after
+    relative: Option<String>,
+    /// Whether we are inside a function body, where only `#[path]` modules can be loaded
+    in_block: bool,
+    path: Vec<String>,
+    enabled: bool,
+}
+
+/// The value of the `#[path = "..."]` attribute among the attributes of a module
+fn path_attr(attrs: &[Meta]) -> Option<String> {
+    attrs.iter().find_map(|meta| match meta {
+        Meta::NameValue(meta) if meta.path.is_ident("path") => match &meta.value {
+            Expr::Lit(expr) => match &expr.lit {
+                Lit::Str(path) => Some(path.value()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// The file an `include!` at item position pulls in, as in `include!("tables.rs")` or
+/// `include!(concat!(env!("OUT_DIR"), "/bindings.rs"))` for code generated by a build script
+fn include_path(mac: &Macro, file_dir: &Path, out_dir: Option<&Path>) -> Option<PathBuf> {
+    if !mac.path.is_ident("include") {
+        return None;
+    }
+    let path = match mac.parse_body::<Expr>().ok()? {
+        Expr::Lit(expr) => match expr.lit {
+            Lit::Str(path) => path.value(),
+            _ => return None,
+        },
+        Expr::Macro(expr) if expr.mac.path.is_ident("concat") => {
+            let parts = expr
+                .mac
+                .parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+                .ok()?;
+            let mut path = String::new();
+            for part in parts {
+                match part {
+                    Expr::Lit(expr) => match expr.lit {
+                        Lit::Str(part) => path.push_str(&part.value()),
+                        _ => return None,
+                    },
+                    Expr::Macro(expr) if expr.mac.path.is_ident("env") => {
+                        if expr.mac.parse_body::<LitStr>().ok()?.value() != "OUT_DIR" {
+                            return None;
+                        }
+                        path.push_str(out_dir?.to_str()?);
+                    }
+                    _ => return None,
+                }
+            }
+            path
+        }
+        _ => return None,
+    };
+    // Joining an absolute path, like one starting with `OUT_DIR`, replaces `file_dir`
+    Some(file_dir.join(path))
+}
+
+impl ModuleWalker<'_> {
+    /// Records `file` as the module `path` and walks it, unless it was already seen. A file first
+    /// seen in a disabled module is taken over by an enabled one, as that is the module it is
+    /// compiled as.
+    fn load(
+        &mut self,
+        file: &Path,
+        dir: PathBuf,
+        relative: Option<String>,
+        path: Vec<String>,
+        enabled: bool,
+    ) {
+        let Ok(file) = file.canonicalize() else {
+            return;
+        };
+        if self
+            .files
+            .get(&file)
+            .is_some_and(|known| known.enabled || !enabled)
+        {
+            return;
+        }
+        self.files.insert(
+            file.clone(),
+            ModuleFile {
+                path: path.clone(),
+                enabled,
+            },
+        );
+        let Some(syntax) = fs::read_to_string(&file)
+            .ok()
+            .and_then(|src| syn::parse_file(&src).ok())
+        else {
+            return;
+        };
+        let mut walker = ModuleWalker {
+            files: self.files,
+            cfg: self.cfg,
+            out_dir: self.out_dir,
+            file_dir: file.parent().map(Path::to_path_buf).unwrap_or_default(),
+            dir,
+            relative,
+            in_block: false,
+            path,
+            enabled: enabled && self.cfg.is_enabled(&syntax.attrs),
+        };
+        visit::Visit::visit_file(&mut walker, &syntax);
+    }
+
+    /// The directory of inline modules declared here, which is where the current file's
+    /// submodules would be
+    fn own_dir(&self) -> PathBuf {
+        match &self.relative {
+            Some(name) => self.dir.join(name),
+            None => self.dir.clone(),
+        }
+    }
+}
+
+impl<'ast> visit::Visit<'ast> for ModuleWalker<'_> {
+    fn visit_item_mod(&mut self, i: &'ast ItemMod) {
+        let mut path = self.path.clone();
+        path.push(i.ident.to_string());
+        // Modules that are not compiled are still walked, so that their files are known not to be
+        let enabled = self.enabled && self.cfg.is_enabled(&i.attrs);
+        let attrs = self.cfg.expand_attrs(&i.attrs);
+        let name = i.ident.to_string();
+
+        if i.content.is_some() {
+            let dir = match path_attr(&attrs) {
+                Some(path_attr) => self.dir.join(path_attr),
+                None => self.own_dir().join(&name),
+            };
+            let old_dir = std::mem::replace(&mut self.dir, dir);
+            let old_relative = self.relative.take();
+            let old_path = std::mem::replace(&mut self.path, path);
+            let old_enabled = std::mem::replace(&mut self.enabled, enabled);
+            visit::visit_item_mod(self, i);
+            self.dir = old_dir;
+            self.relative = old_relative;
+            self.path = old_path;
+            self.enabled = old_enabled;
+            return;
+        }
+
+        if let Some(path_attr) = path_attr(&attrs) {
+            // Files loaded through `#[path]` own their directory, like `mod.rs` files
+            let file = self.dir.join(path_attr);
+            let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+            self.load(&file, dir, None, path, enabled);
+        } else if !self.in_block {
+            let dir = self.own_dir();
+            let file = dir.join(format!("{name}.rs"));
+            if file.exists() {
+                self.load(&file, dir, Some(name), path, enabled);
+            } else {
+                let dir = dir.join(&name);
+                self.load(&dir.join("mod.rs"), dir, None, path, enabled);
+            }
+        }
+    }
+
+    fn visit_item_macro(&mut self, i: &'ast ItemMacro) {
+        // The included items are in the current module
+        if let Some(file) = include_path(&i.mac, &self.file_dir, self.out_dir) {
+            let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+            let enabled = self.enabled && self.cfg.is_enabled(&i.attrs);
+            self.load(&file, dir, None, self.path.clone(), enabled);
+        }
+    }
+
+    fn visit_item_fn(&mut self, i: &'ast ItemFn) {
+        // Items inside a function are named after it, as in `krate::f::inner::g`
+        self.path.push(i.sig.ident.to_string());
+        let was_in_block = std::mem::replace(&mut self.in_block, true);
+        visit::visit_item_fn(self, i);
+        self.in_block = was_in_block;
+        self.path.pop();
+    }
+}
+
+/// Maps the canonicalized path of every file in the module tree of the crate rooted at `root` to
+/// the module it is, evaluating `cfg`s against `cfg`. `out_dir` is the `OUT_DIR` of the package's
+/// build script, which generated files are included from. Files that cannot be read or parsed
+/// are still mapped, but their own submodules are not.
+pub fn module_files(
+    root: &Path,
+    out_dir: Option<&Path>,
+    cfg: &CfgSet,
+) -> HashMap<PathBuf, ModuleFile> {
+    let mut files = HashMap::new();
+    let dir = root.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut walker = ModuleWalker {
+        files: &mut files,
+        cfg,
+        out_dir,
+        file_dir: dir.clone(),
+        dir: dir.clone(),
+        relative: None,
+        in_block: false,
+        path: Vec::new(),
+        enabled: true,
+    };
+    walker.load(root, dir, None, Vec::new(), true);
+    files
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        for (file, src) in [
+            (
+                "lib.rs",
+                r#"
+                mod plain;
+                mod dir_style;
+                #[path = "generated/impl.rs"]
+                mod renamed;
+                mod inline {
+                    mod nested;
+                }
+                #[cfg(test)]
+                mod tests;
+                #[cfg(windows)]
+                mod windows;
+                #[cfg_attr(unix, path = "sys/unix.rs")]
+                mod sys;
+                fn f() {
+                    #[path = "in_fn.rs"]
+                    mod in_fn;
+                }
+                mod ffi {
+                    include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+                }
+                include!("tables.rs");
+                "#,
+            ),
+            ("plain.rs", "mod child;"),
+            ("plain/child.rs", ""),
+            ("dir_style/mod.rs", "mod child;"),
+            ("dir_style/child.rs", ""),
+            ("generated/impl.rs", "mod sibling;"),
+            ("generated/sibling.rs", ""),
+            ("inline/nested.rs", ""),
+            ("tests.rs", "mod helpers;"),
+            ("tests/helpers.rs", ""),
+            ("windows.rs", ""),
+            ("sys/unix.rs", ""),
+            ("in_fn.rs", ""),
+            ("tables.rs", ""),
+            ("out/bindings.rs", ""),
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, src).unwrap();
+        }
+
+        let mut cfg = CfgSet::default();
+        cfg.insert("unix");
+        let files = module_files(&root.join("lib.rs"), Some(&root.join("out")), &cfg);
+        let module = |file: &str| {
+            let module = &files[&root.join(file)];
+            (module.path.join("::"), module.enabled)
+        };
+        assert_eq!(files.len(), 15);
+        assert_eq!(module("lib.rs"), (String::new(), true));
+        assert_eq!(module("plain/child.rs"), ("plain::child".to_string(), true));
+        assert_eq!(
+            module("dir_style/child.rs"),
+            ("dir_style::child".to_string(), true)
+        );
+        assert_eq!(module("generated/impl.rs"), ("renamed".to_string(), true));
+        assert_eq!(
+            module("generated/sibling.rs"),
+            ("renamed::sibling".to_string(), true)
+        );
+        assert_eq!(
+            module("inline/nested.rs"),
+            ("inline::nested".to_string(), true)
+        );
+        assert_eq!(module("tests.rs"), ("tests".to_string(), false));
+        assert_eq!(
+            module("tests/helpers.rs"),
+            ("tests::helpers".to_string(), false)
+        );
+        assert_eq!(module("windows.rs"), ("windows".to_string(), false));
+        assert_eq!(module("sys/unix.rs"), ("sys".to_string(), true));
+        assert_eq!(module("in_fn.rs"), ("f::in_fn".to_string(), true));
+        assert_eq!(module("tables.rs"), (String::new(), true));
+        assert_eq!(module("out/bindings.rs"), ("ffi".to_string(), true));
+    }
+
+    #[test]
+    fn test_file_of_disabled_and_enabled_modules() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        for (file, src) in [
+            (
+                "lib.rs",
+                r#"
+                #[cfg(windows)]
+                #[path = "shared.rs"]
+                mod windows;
+                #[path = "shared.rs"]
+                mod unix;
+                "#,
+            ),
+            ("shared.rs", "mod child;"),
+            ("child.rs", ""),
+        ] {
+            fs::write(root.join(file), src).unwrap();
+        }
+
+        let files = module_files(&root.join("lib.rs"), None, &CfgSet::default());
+        let module = |file: &str| {
+            let module = &files[&root.join(file)];
+            (module.path.join("::"), module.enabled)
+        };
+        assert_eq!(module("shared.rs"), ("unix".to_string(), true));
+        assert_eq!(module("child.rs"), ("unix::child".to_string(), true));
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The syntactic construct an unsafe site was found in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum UnsafeKind {
+    /// A free-standing `unsafe fn` declaration
+    Function,
+    /// An `unsafe { ... }` block
+    Block,
+    /// An `unsafe fn` in an inherent impl
+    Method,
+    /// An `unsafe fn` in a trait impl
+    TraitMethod,
+    /// An `unsafe fn` declared in a trait without a default body. It has no code of its own,
+    /// every implementation of it is a `TraitMethod`.
+    TraitFn,
+    /// An `unsafe fn` with a default body in a trait
+    DefaultMethod,
+    /// An `unsafe impl` of an unsafe trait, like `Send` or `Sync`
+    UnsafeImpl,
+    /// An `unsafe trait` declaration
+    UnsafeTrait,
+    /// A `static mut` item
+    StaticMut,
+    /// A `union` definition, whose fields can only be read in unsafe code
+    Union,
+    /// An `extern` block, or an `unsafe extern` block, declaring foreign items
+    ExternBlock,
+    /// An unsafe attribute, like `#[unsafe(no_mangle)]` or `#[no_mangle]` before Rust 2024
+    UnsafeAttribute,
+    /// An `unsafe` block in the initializer of a `static` or a `const`, which is evaluated at
+    /// compile time
+    InitializerBlock,
+}
+
+impl UnsafeKind {
+    pub const ALL: [Self; 13] = [
+        Self::Function,
+        Self::Block,
+        Self::Method,
+        Self::TraitMethod,
+        Self::TraitFn,
+        Self::DefaultMethod,
+        Self::UnsafeImpl,
+        Self::UnsafeTrait,
+        Self::StaticMut,
+        Self::Union,
+        Self::ExternBlock,
+        Self::UnsafeAttribute,
+        Self::InitializerBlock,
+    ];
+
+    /// A short identifier for the kind, suitable for rule ids and machine-readable output
+    #[must_use]
+    pub const fn id(self) -> &'static str {
+        match self {
+            Self::Function => "unsafe-fn",
+            Self::Block => "unsafe-block",
+            Self::Method => "unsafe-method",
+            Self::TraitMethod => "unsafe-trait-method",
+            Self::TraitFn => "unsafe-trait-fn",
+            Self::DefaultMethod => "unsafe-default-method",
+            Self::UnsafeImpl => "unsafe-impl",
+            Self::UnsafeTrait => "unsafe-trait",
+            Self::StaticMut => "static-mut",
+            Self::Union => "union",
+            Self::ExternBlock => "extern-block",
+            Self::UnsafeAttribute => "unsafe-attribute",
+            Self::InitializerBlock => "unsafe-initializer-block",
+        }
+    }
+
+    /// Whether sites of this kind are code that runs, and so can be found in the callgraph.
+    /// Declarations and what is evaluated at compile time are only listed.
+    #[must_use]
+    pub const fn has_code(self) -> bool {
+        matches!(
+            self,
+            Self::Function | Self::Block | Self::Method | Self::TraitMethod | Self::DefaultMethod
+        )
+    }
+}
+
+impl fmt::Display for UnsafeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Function => "unsafe fn",
+            Self::Block => "unsafe block",
+            Self::Method => "unsafe method",
+            Self::TraitMethod => "unsafe trait method",
+            Self::TraitFn => "unsafe fn declaration in a trait",
+            Self::DefaultMethod => "unsafe default method",
+            Self::UnsafeImpl => "unsafe impl",
+            Self::UnsafeTrait => "unsafe trait",
+            Self::StaticMut => "static mut",
+            Self::Union => "union",
+            Self::ExternBlock => "extern block",
+            Self::UnsafeAttribute => "unsafe attribute",
+            Self::InitializerBlock => "unsafe block in an initializer",
+        })
+    }
+}
+
+/// What an operation that needs `unsafe` does
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum OpCategory {
+    /// Dereferencing what is a raw pointer by its syntax, as in `*(ptr as *const u8)` or
+    /// `*v.as_ptr()`
+    RawPointerDeref,
+    /// Dereferencing something that may be a raw pointer or a reference, as in `*ptr`, whose
+    /// type is not known without type checking
+    UnknownDeref,
+    /// Calling an `unsafe fn` that is not foreign, like `ptr::read`
+    UnsafeCall,
+    /// Calling a function declared in an `extern` block, or from a `-sys` crate
+    FfiCall,
+    /// Calling `mem::transmute` or `mem::transmute_copy`
+    Transmute,
+    /// Reading or writing a `static mut`, or a static declared in an `extern` block
+    StaticMutAccess,
+    /// Reading a field of a union
+    UnionFieldRead,
+    /// Inline assembly with `asm!`
+    InlineAsm,
+    /// Calling a function that skips a check the caller must uphold instead, like
+    /// `get_unchecked`, `set_len` or `from_raw_parts`
+    UncheckedCall,
+}
+
+impl OpCategory {
+    pub const ALL: [Self; 9] = [
+        Self::RawPointerDeref,
+        Self::UnknownDeref,
+        Self::UnsafeCall,
+        Self::FfiCall,
+        Self::Transmute,
+        Self::StaticMutAccess,
+        Self::UnionFieldRead,
+        Self::InlineAsm,
+        Self::UncheckedCall,
+    ];
+
+    /// A short identifier for the category, used on the command line and in machine-readable
+    /// output
+    #[must_use]
+    pub const fn id(self) -> &'static str {
+        match self {
+            Self::RawPointerDeref => "deref",
+            Self::UnknownDeref => "unknown-deref",
+            Self::UnsafeCall => "unsafe-call",
+            Self::FfiCall => "ffi-call",
+            Self::Transmute => "transmute",
+            Self::StaticMutAccess => "static-mut",
+            Self::UnionFieldRead => "union-field",
+            Self::InlineAsm => "asm",
+            Self::UncheckedCall => "unchecked",
+        }
+    }
+}
+
+impl fmt::Display for OpCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::RawPointerDeref => "raw pointer dereference",
+            Self::UnknownDeref => "dereference of unknown type",
+            Self::UnsafeCall => "call to an unsafe fn",
+            Self::FfiCall => "FFI call",
+            Self::Transmute => "transmute",
+            Self::StaticMutAccess => "static mut access",
+            Self::UnionFieldRead => "union field read",
+            Self::InlineAsm => "inline assembly",
+            Self::UncheckedCall => "unchecked call",
+        })
+    }
+}
+
+impl FromStr for OpCategory {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|category| category.id() == s)
+            .ok_or_else(|| format!("Unknown unsafe operation category `{s}`"))
+    }
+}
+
+/// A region of a source file. Lines and columns are 1-based and the end is inclusive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceSpan {
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+/// The default body of a trait method, of which every implementor that does not override it gets
+/// its own copy, named like `<foo::Bar as Baz>::f`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DefaultMethod {
+    /// The name of the trait, without its path, e.g. `Baz`
+    pub trait_name: String,
+    /// The path of the function enclosing the site inside the trait, e.g. `f` or
+    /// `f::{{closure}}`
+    pub item: String,
+}
+
+impl DefaultMethod {
+    /// Whether `short_label`, whose trait paths are simplified, is the copy of the function of
+    /// some implementor
+    #[must_use]
+    pub fn is_copied_to(&self, short_label: &str) -> bool {
+        short_label
+            .strip_suffix(&self.item)
+            .and_then(|label| label.strip_suffix(">::"))
+            .and_then(|label| label.rsplit_once(" as "))
+            .is_some_and(|(_, trait_path)| {
+                trait_path
+                    .strip_prefix(&self.trait_name)
+                    .is_some_and(|generics| generics.is_empty() || generics.starts_with('<'))
+            })
+    }
+}
+
+/// An operation inside an unsafe site that needs `unsafe`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UnsafeOperation {
+    pub category: OpCategory,
+    pub span: SourceSpan,
+}
+
+/// A place in the source code where `unsafe` is used
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UnsafeSite {
+    /// Path of the innermost function (or closure) enclosing the site, formatted like a
+    /// demangled LLVM label, e.g. `<foo::Bar as Baz>::f` or `foo::f::{{closure}}`
+    pub item: String,
+    pub kind: UnsafeKind,
+    /// The canonicalized path of the file the site is in
+    pub file: PathBuf,
+    pub span: SourceSpan,
+    /// The name of the crate the site is in, as used in paths
+    pub crate_name: String,
+    /// The name of the package the site is in
+    pub package_name: String,
+    /// The version of the package the site is in
+    pub package_version: String,
+    /// Set if the site is in the default body of a trait method. `item` then names the
+    /// function as declared in the trait, like `foo::Baz::f`.
+    pub default_method: Option<DefaultMethod>,
+    /// The operations of the site's code that need `unsafe`, in source order. Only unsafe
+    /// blocks and the bodies of unsafe functions have operations.
+    pub operations: Vec<UnsafeOperation>,
+}
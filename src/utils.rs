@@ -1,5 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
+use std::path::PathBuf;
 use std::process::Command;
 
 // This funciton takes a Rust module path like
@@ -38,8 +40,9 @@ pub fn simplify_trait_paths(path: &str) -> String {
 }
 
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
-    use crate::utils::simplify_trait_paths;
+    use crate::utils::{simplify_trait_paths, CallGraph, CallKind, CallSites, IndirectCall};
 
     #[test]
     fn test_1() {
@@ -63,18 +66,394 @@ mod tests {
             "<T as AsFail>::as_fail"
         );
     }
+
+    #[test]
+    fn test_extend_merges_labels_across_modules() {
+        let mut root = CallGraph::default();
+        // The root crate calls into a dependency it only has a declaration for
+        root.add_call("root::main", "dep::f::h0123456789abcdef", CallKind::Direct);
+
+        let mut dep = CallGraph::default();
+        dep.add_call(
+            "dep::g::hfedcba9876543210",
+            "dep::f::h0123456789abcdef",
+            CallKind::Direct,
+        );
+        dep.label_to_label_info
+            .get_mut("dep::f::h0123456789abcdef")
+            .unwrap()
+            .short_label = Some("dep::f".to_string());
+        dep.short_label_to_labels
+            .entry("dep::f".to_string())
+            .or_default()
+            .insert("dep::f::h0123456789abcdef".to_string());
+
+        root.extend(dep);
+
+        let merged = &root.label_to_label_info["dep::f::h0123456789abcdef"];
+        assert_eq!(merged.short_label.as_deref(), Some("dep::f"));
+        assert_eq!(merged.caller_labels.len(), 2);
+        assert!(root.short_label_to_labels["dep::f"].contains("dep::f::h0123456789abcdef"));
+    }
+
+    #[test]
+    fn test_resolve_virtual_calls() {
+        let mut callgraph = CallGraph::default();
+        for (label, signature) in [
+            ("<a::A as Read>::read", "i64 (ptr, ptr, i64)"),
+            ("<b::B as Read>::read", "i64 (ptr, ptr, i64)"),
+            ("<c::C as Display>::fmt", "i1 (ptr, ptr)"),
+        ] {
+            callgraph
+                .label_to_label_info
+                .entry(label.to_string())
+                .or_default()
+                .signature = Some(signature.to_string());
+        }
+        callgraph.vtable_slots.insert(
+            24,
+            [
+                "<a::A as Read>::read",
+                "<b::B as Read>::read",
+                "<c::C as Display>::fmt",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        );
+        // `a::A::read` is also called directly, which takes precedence over the virtual call
+        callgraph.add_call("root::main", "<a::A as Read>::read", CallKind::Direct);
+        callgraph.indirect_calls.insert(
+            IndirectCall {
+                caller: "root::main".to_string(),
+                vtable_offset: Some(24),
+                signature: "i64 (ptr, ptr, i64)".to_string(),
+            },
+            CallSites::one(false, None),
+        );
+
+        callgraph.resolve_indirect_calls();
+
+        let caller_kind = |label: &str| {
+            callgraph.label_to_label_info[label]
+                .caller_labels
+                .get("root::main")
+                .map(|edge| edge.kind)
+        };
+        assert_eq!(caller_kind("<a::A as Read>::read"), Some(CallKind::Direct));
+        assert_eq!(caller_kind("<b::B as Read>::read"), Some(CallKind::Virtual));
+        assert_eq!(caller_kind("<c::C as Display>::fmt"), None);
+    }
+
+    #[test]
+    fn test_resolve_function_pointer_calls() {
+        let mut callgraph = CallGraph::default();
+        callgraph
+            .address_taken
+            .insert("dep::on_read".to_string(), "void (ptr, i64)".to_string());
+        callgraph
+            .address_taken
+            .insert("dep::on_close".to_string(), "void (ptr)".to_string());
+        callgraph.indirect_calls.insert(
+            IndirectCall {
+                caller: "dep::dispatch".to_string(),
+                vtable_offset: None,
+                signature: "void (ptr, i64)".to_string(),
+            },
+            CallSites {
+                count: 2,
+                in_loop: 1,
+                debuglocs: vec![],
+            },
+        );
+
+        callgraph.resolve_indirect_calls();
+
+        let edge = &callgraph.label_to_label_info["dep::on_read"].caller_labels["dep::dispatch"];
+        assert_eq!(edge.kind, CallKind::AddressTaken);
+        assert_eq!(edge.sites.count, 2);
+        assert_eq!(edge.sites.weight(10), 11);
+        assert!(!callgraph.label_to_label_info.contains_key("dep::on_close"));
+    }
+}
+
+/// How a call edge was found
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CallKind {
+    /// The callee is named at the call site
+    Direct,
+    /// A `dyn Trait` method call, through a vtable slot that may hold the callee
+    Virtual,
+    /// A call through a function pointer that may point to the callee, because the callee's
+    /// address is taken somewhere and its signature matches
+    AddressTaken,
+}
+
+impl CallKind {
+    /// Whether the edge is only a conservative guess at what may be called
+    #[must_use]
+    pub const fn is_indirect(self) -> bool {
+        !matches!(self, Self::Direct)
+    }
+
+    /// A short description for the output
+    #[must_use]
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::Direct => "direct call",
+            Self::Virtual => "indirect, dynamic dispatch",
+            Self::AddressTaken => "approximate, function pointer",
+        }
+    }
+}
+
+/// Where and how often one function calls another
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallSites {
+    /// The number of call sites
+    pub count: u32,
+    /// How many of them are inside a loop of the caller
+    pub in_loop: u32,
+    /// The location of every call site that has debug info
+    pub debuglocs: Vec<llvm_ir::DebugLoc>,
+}
+
+impl CallSites {
+    /// A single call site
+    #[must_use]
+    pub fn one(in_loop: bool, debugloc: Option<&llvm_ir::DebugLoc>) -> Self {
+        Self {
+            count: 1,
+            in_loop: u32::from(in_loop),
+            debuglocs: debugloc.into_iter().cloned().collect(),
+        }
+    }
+
+    /// Adds the call sites of `other` to these
+    pub fn extend(&mut self, other: &Self) {
+        self.count = self.count.saturating_add(other.count);
+        self.in_loop = self.in_loop.saturating_add(other.in_loop);
+        self.debuglocs.extend_from_slice(&other.debuglocs);
+    }
+
+    /// How much the calls weigh if every call site counts once, and `loop_weight` times if it
+    /// is inside a loop
+    #[must_use]
+    pub const fn weight(&self, loop_weight: u32) -> u32 {
+        self.count
+            .saturating_sub(self.in_loop)
+            .saturating_add(self.in_loop.saturating_mul(loop_weight))
+    }
+}
+
+/// The calls from one function to another
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallEdge {
+    pub kind: CallKind,
+    pub sites: CallSites,
+}
+
+/// A call through a function pointer. Which functions it may call is only known once all
+/// modules are merged, see `CallGraph::resolve_indirect_calls`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IndirectCall {
+    pub caller: String,
+    /// The byte offset of the vtable slot the pointer is loaded from, if it comes from a vtable
+    pub vtable_offset: Option<u64>,
+    /// The LLVM type of the called function
+    pub signature: String,
 }
 
 #[derive(Clone, Default)]
 pub struct LabelInfo {
+    /// The full demangled label, only known for functions defined in some module
+    pub label: Option<String>,
     pub short_label: Option<String>,
-    pub caller_labels: HashSet<String>,
+    /// Maps every function calling this one to its calls
+    pub caller_labels: HashMap<String, CallEdge>,
     pub debugloc: Option<llvm_ir::DebugLoc>,
+    /// The canonicalized file of `debugloc`, if it exists on this machine
+    pub defined_in: Option<PathBuf>,
+    /// The LLVM type of the function, only known for functions defined in some module
+    pub signature: Option<String>,
+    /// The number of calls to each `llvm.*` intrinsic this function makes. Intrinsics are not
+    /// functions of the program, so they are kept out of the callgraph.
+    pub intrinsics: BTreeMap<String, u32>,
+    /// The labels of the root targets whose modules define this function
+    pub targets: BTreeSet<String>,
+}
+
+impl LabelInfo {
+    /// Folds the information another module has about the same label into this one
+    fn merge(&mut self, other: Self) {
+        if self.label.is_none() {
+            self.label = other.label;
+        }
+        if self.short_label.is_none() {
+            self.short_label = other.short_label;
+        }
+        if self.debugloc.is_none() {
+            self.debugloc = other.debugloc;
+            self.defined_in = other.defined_in;
+        }
+        if self.signature.is_none() {
+            self.signature = other.signature;
+        }
+        if self.intrinsics.is_empty() {
+            self.intrinsics = other.intrinsics;
+        }
+        self.targets.extend(other.targets);
+        // Inlinable functions are instantiated in every module using them, so the same calls can
+        // come from several modules
+        for (caller, edge) in other.caller_labels {
+            match self.caller_labels.entry(caller) {
+                Entry::Occupied(mut existing) => {
+                    let existing = existing.get_mut();
+                    existing.kind = existing.kind.min(edge.kind);
+                    if edge.sites.count > existing.sites.count {
+                        existing.sites = edge.sites;
+                    }
+                }
+                Entry::Vacant(vacant) => {
+                    vacant.insert(edge);
+                }
+            }
+        }
+    }
+
+    /// Records more calls from `caller`. If they are found in several ways, the most direct one
+    /// wins.
+    fn add_caller(&mut self, caller: String, kind: CallKind, sites: &CallSites) {
+        let edge = self
+            .caller_labels
+            .entry(caller)
+            .or_insert_with(|| CallEdge {
+                kind,
+                sites: CallSites::default(),
+            });
+        edge.kind = edge.kind.min(kind);
+        edge.sites.extend(sites);
+    }
 }
 
+#[derive(Default)]
 pub struct CallGraph {
     pub label_to_label_info: HashMap<String, LabelInfo>,
     pub short_label_to_labels: HashMap<String, HashSet<String>>,
+    /// For every canonicalized source file, the labels of the functions with instructions on
+    /// each line, according to debug info
+    pub file_to_line_to_labels: HashMap<PathBuf, HashMap<u32, HashSet<String>>>,
+    /// For every byte offset into a vtable, the labels of the methods some vtable has there
+    pub vtable_slots: HashMap<u64, HashSet<String>>,
+    /// Maps every function whose address escapes, e.g. into a variable, an argument or a
+    /// constant table, to its signature
+    pub address_taken: HashMap<String, String>,
+    /// The calls through function pointers that are not resolved yet, with their call sites
+    pub indirect_calls: HashMap<IndirectCall, CallSites>,
+}
+
+impl CallGraph {
+    /// Merges the callgraph of another bitcode module into this one. Labels are full demangled
+    /// symbols, so a function declared in one module and defined in another ends up as one node.
+    pub fn extend(&mut self, other: Self) {
+        for (label, label_info) in other.label_to_label_info {
+            self.label_to_label_info
+                .entry(label)
+                .or_default()
+                .merge(label_info);
+        }
+        for (short_label, labels) in other.short_label_to_labels {
+            self.short_label_to_labels
+                .entry(short_label)
+                .or_default()
+                .extend(labels);
+        }
+        for (file, line_to_labels) in other.file_to_line_to_labels {
+            let self_line_to_labels = self.file_to_line_to_labels.entry(file).or_default();
+            for (line, labels) in line_to_labels {
+                self_line_to_labels.entry(line).or_default().extend(labels);
+            }
+        }
+        for (offset, labels) in other.vtable_slots {
+            self.vtable_slots.entry(offset).or_default().extend(labels);
+        }
+        self.address_taken.extend(other.address_taken);
+        for (call, sites) in other.indirect_calls {
+            let existing = self.indirect_calls.entry(call).or_default();
+            if sites.count > existing.count {
+                *existing = sites;
+            }
+        }
+    }
+
+    /// Records a call site in `caller` calling `callee`, outside of any loop
+    #[cfg(test)]
+    #[allow(clippy::similar_names)]
+    pub fn add_call(&mut self, caller: &str, callee: &str, kind: CallKind) {
+        self.add_calls(caller, callee, kind, &CallSites::one(false, None));
+    }
+
+    /// Records the call sites in `caller` calling `callee`
+    #[allow(clippy::similar_names)]
+    pub fn add_calls(&mut self, caller: &str, callee: &str, kind: CallKind, sites: &CallSites) {
+        self.label_to_label_info
+            .entry(callee.to_string())
+            .or_default()
+            .add_caller(caller.to_string(), kind, sites);
+    }
+
+    /// Connects every indirect call to the functions it may call.
+    ///
+    /// Rust does not tell which trait a vtable is for, so a call through a vtable may call all
+    /// methods at the same offset of any vtable whose signature matches the call. Methods whose
+    /// signature is unknown are kept, to err on the side of reporting unsafety. Any other call
+    /// through a function pointer may call every function whose address is taken and whose
+    /// signature matches.
+    pub fn resolve_indirect_calls(&mut self) {
+        let mut edges = Vec::new();
+        for (call, sites) in std::mem::take(&mut self.indirect_calls) {
+            if let Some(offset) = call.vtable_offset {
+                for callee in self.vtable_slots.get(&offset).into_iter().flatten() {
+                    let signature = self
+                        .label_to_label_info
+                        .get(callee)
+                        .and_then(|label_info| label_info.signature.as_ref());
+                    if signature.is_none_or(|signature| *signature == call.signature) {
+                        edges.push((
+                            call.caller.clone(),
+                            callee.clone(),
+                            CallKind::Virtual,
+                            sites.clone(),
+                        ));
+                    }
+                }
+            } else {
+                for (callee, signature) in &self.address_taken {
+                    if *signature == call.signature {
+                        edges.push((
+                            call.caller.clone(),
+                            callee.clone(),
+                            CallKind::AddressTaken,
+                            sites.clone(),
+                        ));
+                    }
+                }
+            }
+        }
+        for (caller, callee, kind, sites) in edges {
+            self.add_calls(&caller, &callee, kind, &sites);
+        }
+    }
+}
+
+/// The file a debug location points to. Relative filenames are relative to the compilation
+/// directory.
+#[must_use]
+pub fn debugloc_path(debugloc: &llvm_ir::DebugLoc) -> PathBuf {
+    debugloc.directory.as_ref().map_or_else(
+        || PathBuf::from(&debugloc.filename),
+        |directory| PathBuf::from(directory).join(&debugloc.filename),
+    )
 }
 
 #[allow(clippy::missing_panics_doc, clippy::expect_used, clippy::unwrap_used)]
@@ -0,0 +1,11 @@
+pub struct Cell(pub u8);
+
+impl Cell {
+    pub fn as_ptr(&self) -> *const u8 {
+        &self.0
+    }
+}
+
+pub fn peek(cell: &Cell) -> u8 {
+    unsafe { *cell.as_ptr() }
+}
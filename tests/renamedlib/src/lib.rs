@@ -0,0 +1,18 @@
+// This test checks if Siderophile works fine on crates whose library is renamed, and that use a
+// dependency renamed in Cargo.toml.
+//
+use helper::Cell;
+
+pub trait Poke {
+    fn poke(&self) -> u8;
+}
+
+impl Poke for helper::Cell {
+    fn poke(&self) -> u8 {
+        unsafe { *self.as_ptr() }
+    }
+}
+
+pub fn frob(cell: &Cell) -> u8 {
+    cell.poke().wrapping_add(helper::peek(cell))
+}